@@ -0,0 +1,51 @@
+-- Посты источников, дошедшие до финала
+CREATE TABLE IF NOT EXISTS processed_messages (
+    chat_id      BIGINT      NOT NULL,
+    message_id   INTEGER     NOT NULL,
+    processed_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (chat_id, message_id)
+);
+
+-- Последний обработанный id каждого источника
+CREATE TABLE IF NOT EXISTS high_water (
+    chat_id    BIGINT  PRIMARY KEY,
+    message_id INTEGER NOT NULL
+);
+
+-- Посты в работе и dead letters
+CREATE TABLE IF NOT EXISTS outbox (
+    chat_id    BIGINT      NOT NULL,
+    message_id INTEGER     NOT NULL,
+    entry      JSONB       NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (chat_id, message_id)
+);
+
+-- Решения классификатора
+CREATE TABLE IF NOT EXISTS decisions (
+    id         UUID        PRIMARY KEY DEFAULT gen_random_uuid(),
+    chat_id    BIGINT      NOT NULL,
+    message_id INTEGER     NOT NULL,
+    relevant   BOOLEAN     NOT NULL,
+    text       TEXT,
+    model      TEXT        NOT NULL,
+    decided_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS decisions_message_idx ON decisions (chat_id, message_id);
+
+-- Копии постов источника в target каналах
+CREATE TABLE IF NOT EXISTS message_mapping (
+    source_chat_id     BIGINT      NOT NULL,
+    source_message_id  INTEGER     NOT NULL,
+    target_chat_id     BIGINT      NOT NULL,
+    target_message_ids INTEGER[]   NOT NULL,
+    created_at         TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (source_chat_id, source_message_id, target_chat_id)
+);
+
+-- Служебные отметки, например что history.json уже импортирован
+CREATE TABLE IF NOT EXISTS storage_meta (
+    key   TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
@@ -0,0 +1,2 @@
+-- Категория из ответа модели, по ней маршруты выбирают target
+ALTER TABLE decisions ADD COLUMN IF NOT EXISTS category TEXT;
@@ -0,0 +1,2 @@
+-- Дата правки оригинала, с которой опубликована копия, по ней замечаем новые правки
+ALTER TABLE message_mapping ADD COLUMN IF NOT EXISTS source_edit_date BIGINT;
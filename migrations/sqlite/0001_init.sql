@@ -0,0 +1,51 @@
+-- Посты источников, дошедшие до финала
+CREATE TABLE IF NOT EXISTS processed_messages (
+    chat_id      INTEGER NOT NULL,
+    message_id   INTEGER NOT NULL,
+    processed_at TEXT    NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (chat_id, message_id)
+) WITHOUT ROWID;
+
+-- Последний обработанный id каждого источника
+CREATE TABLE IF NOT EXISTS high_water (
+    chat_id    INTEGER PRIMARY KEY,
+    message_id INTEGER NOT NULL
+);
+
+-- Посты в работе и dead letters, запись outbox в JSON
+CREATE TABLE IF NOT EXISTS outbox (
+    chat_id    INTEGER NOT NULL,
+    message_id INTEGER NOT NULL,
+    entry      TEXT    NOT NULL,
+    updated_at TEXT    NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (chat_id, message_id)
+);
+
+-- Решения классификатора
+CREATE TABLE IF NOT EXISTS decisions (
+    id         INTEGER PRIMARY KEY AUTOINCREMENT,
+    chat_id    INTEGER NOT NULL,
+    message_id INTEGER NOT NULL,
+    relevant   INTEGER NOT NULL,
+    text       TEXT,
+    model      TEXT    NOT NULL,
+    decided_at TEXT    NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS decisions_message_idx ON decisions (chat_id, message_id);
+
+-- Копии постов источника в target каналах, id сообщений — JSON массив
+CREATE TABLE IF NOT EXISTS message_mapping (
+    source_chat_id     INTEGER NOT NULL,
+    source_message_id  INTEGER NOT NULL,
+    target_chat_id     INTEGER NOT NULL,
+    target_message_ids TEXT    NOT NULL,
+    created_at         TEXT    NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (source_chat_id, source_message_id, target_chat_id)
+);
+
+-- Служебные отметки, например что history.json уже импортирован
+CREATE TABLE IF NOT EXISTS storage_meta (
+    key   TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
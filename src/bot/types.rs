@@ -0,0 +1,218 @@
+//! Типизированная модель поста.
+//!
+//! [`PostKind`] описывает, что за вложение у поста источника, какой текст по нему
+//! показываем модели и как пост копируется. [`OutgoingPost`] — то, что в итоге
+//! отправляется в target.
+
+use crate::transport::{
+    split::{CAPTION_LIMIT, TEXT_LIMIT},
+    AlbumItem,
+};
+
+/// Вид поста источника
+#[derive(Debug, Clone, PartialEq)]
+pub enum PostKind {
+    Text,
+    /// Текст со ссылкой, для которой Telegram показал превью
+    WebPage {
+        url: String,
+        title: Option<String>,
+        description: Option<String>,
+    },
+    Photo,
+    Video,
+    /// GIF, в Telegram это mp4 без звука
+    Animation,
+    Audio {
+        title: Option<String>,
+        performer: Option<String>,
+    },
+    Voice,
+    /// Видеосообщение (кружок)
+    VideoNote,
+    Document {
+        file_name: Option<String>,
+    },
+    Sticker {
+        emoji: String,
+    },
+    Poll {
+        question: String,
+        answers: Vec<String>,
+    },
+    Geo {
+        latitude: f64,
+        longitude: f64,
+    },
+    Venue {
+        title: String,
+        address: String,
+    },
+    Contact {
+        name: String,
+        phone: String,
+    },
+    Dice {
+        emoji: String,
+    },
+    /// Игры, счета, истории и прочее, что бот не копирует
+    Unsupported,
+}
+
+/// Что отправляем в target канал
+#[derive(Debug, Clone)]
+pub enum OutgoingPost<M> {
+    /// Текст в разметке транспорта. `link_preview` — показать превью ссылки, как в источнике
+    Text { text: String, link_preview: bool },
+    /// Одно вложение. У видов без подписи `caption` пуст
+    Media { media: M, caption: String },
+    Album(Vec<AlbumItem<M>>),
+    /// Пересылка постов `ids` из `chat_id` как есть, альбом — одной группой
+    Forward { chat_id: i64, ids: Vec<i32> },
+}
+
+impl PostKind {
+    /// Лимит публикуемого текста. `None` — текст поста не публикуется (стикер, опрос, гео...)
+    pub fn text_limit(&self) -> Option<usize> {
+        match self {
+            PostKind::Text | PostKind::WebPage { .. } => Some(TEXT_LIMIT),
+            PostKind::Photo
+            | PostKind::Video
+            | PostKind::Animation
+            | PostKind::Audio { .. }
+            | PostKind::Voice
+            | PostKind::Document { .. } => Some(CAPTION_LIMIT),
+            _ => None,
+        }
+    }
+
+    /// Текст для классификации: текст поста и описание вложения, которого модель
+    /// иначе не увидит. Картинки фото уходят модели отдельно
+    pub fn classification_text(&self, text: &str) -> String {
+        match self.describe() {
+            Some(description) if text.is_empty() => description,
+            Some(description) => format!("{}\n\n{}", text, description),
+            None => text.to_string(),
+        }
+    }
+
+    fn describe(&self) -> Option<String> {
+        let description = match self {
+            PostKind::Text | PostKind::Photo | PostKind::Unsupported => return None,
+            PostKind::WebPage {
+                url,
+                title,
+                description,
+            } => {
+                let details: Vec<&str> = [title.as_deref(), description.as_deref()].into_iter().flatten().collect();
+                format!("[Превью ссылки {}: {}]", url, details.join(" — "))
+            }
+            PostKind::Video => "[Видео]".to_string(),
+            PostKind::Animation => "[GIF]".to_string(),
+            PostKind::Audio { title, performer } => {
+                let details: Vec<&str> = [performer.as_deref(), title.as_deref()].into_iter().flatten().collect();
+                format!("[Аудио: {}]", details.join(" — "))
+            }
+            PostKind::Voice => "[Голосовое сообщение]".to_string(),
+            PostKind::VideoNote => "[Видеосообщение]".to_string(),
+            PostKind::Document { file_name } => format!("[Файл: {}]", file_name.as_deref().unwrap_or("без имени")),
+            PostKind::Sticker { emoji } => format!("[Стикер {}]", emoji),
+            PostKind::Poll { question, answers } => format!("[Опрос: {}]\n- {}", question, answers.join("\n- ")),
+            PostKind::Geo { latitude, longitude } => format!("[Геопозиция: {}, {}]", latitude, longitude),
+            PostKind::Venue { title, address } => format!("[Место: {}, {}]", title, address),
+            PostKind::Contact { name, phone } => format!("[Контакт: {}, {}]", name, phone),
+            PostKind::Dice { emoji } => format!("[Кубик {}]", emoji),
+        };
+        Some(description)
+    }
+
+    /// Как копируем пост с текстом `text` (ответ модели или оригинал).
+    /// `None` — копировать нечего
+    pub fn outgoing<M>(&self, text: String, media: Option<M>) -> Option<OutgoingPost<M>> {
+        match (self, media) {
+            (PostKind::Unsupported, _) => None,
+            (PostKind::Text, _) => Some(OutgoingPost::Text {
+                text,
+                link_preview: false,
+            }),
+            // Превью Telegram построит заново по ссылке из текста
+            (PostKind::WebPage { .. }, _) => Some(OutgoingPost::Text {
+                text,
+                link_preview: true,
+            }),
+            (kind, Some(media)) if kind.text_limit().is_some() => Some(OutgoingPost::Media { media, caption: text }),
+            // Стикеры, кружки, опросы, гео и контакты копируются без подписи
+            (_, Some(media)) => Some(OutgoingPost::Media {
+                media,
+                caption: String::new(),
+            }),
+            (_, None) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Исходящий пост строкой: текст с флагом превью или вложение с подписью
+    fn shape(post: Option<OutgoingPost<&str>>) -> Option<String> {
+        post.map(|post| match post {
+            OutgoingPost::Text { text, link_preview } => format!("text {:?}, preview {}", text, link_preview),
+            OutgoingPost::Media { media, caption } => format!("{} {:?}", media, caption),
+            OutgoingPost::Album(_) | OutgoingPost::Forward { .. } => unreachable!("kinds never produce these"),
+        })
+    }
+
+    #[test]
+    fn kinds_describe_limit_and_copy() {
+        let web_page = PostKind::WebPage {
+            url: "https://example.com".to_string(),
+            title: Some("Пример".to_string()),
+            description: None,
+        };
+        let audio = PostKind::Audio {
+            title: Some("Песня".to_string()),
+            performer: Some("Группа".to_string()),
+        };
+        let poll = PostKind::Poll {
+            question: "Да?".to_string(),
+            answers: vec!["да".to_string(), "нет".to_string()],
+        };
+        let cases = [
+            (PostKind::Text, None, "Пост", Some(TEXT_LIMIT), Some(r#"text "Пост", preview false"#)),
+            (
+                web_page,
+                None,
+                "Пост\n\n[Превью ссылки https://example.com: Пример]",
+                Some(TEXT_LIMIT),
+                Some(r#"text "Пост", preview true"#),
+            ),
+            (PostKind::Photo, Some("photo"), "Пост", Some(CAPTION_LIMIT), Some(r#"photo "Пост""#)),
+            (PostKind::Video, Some("video"), "Пост\n\n[Видео]", Some(CAPTION_LIMIT), Some(r#"video "Пост""#)),
+            (audio, Some("audio"), "Пост\n\n[Аудио: Группа — Песня]", Some(CAPTION_LIMIT), Some(r#"audio "Пост""#)),
+            (
+                PostKind::Document { file_name: None },
+                Some("file"),
+                "Пост\n\n[Файл: без имени]",
+                Some(CAPTION_LIMIT),
+                Some(r#"file "Пост""#),
+            ),
+            (PostKind::VideoNote, Some("note"), "Пост\n\n[Видеосообщение]", None, Some(r#"note """#)),
+            (poll, Some("poll"), "Пост\n\n[Опрос: Да?]\n- да\n- нет", None, Some(r#"poll """#)),
+            (PostKind::Sticker { emoji: "🙂".to_string() }, None, "Пост\n\n[Стикер 🙂]", None, None),
+            (PostKind::Unsupported, Some("game"), "Пост", None, None),
+        ];
+        for (kind, media, classification, limit, outgoing) in cases {
+            assert_eq!(kind.classification_text("Пост"), classification, "{:?}", kind);
+            assert_eq!(kind.text_limit(), limit, "{:?}", kind);
+            assert_eq!(shape(kind.outgoing("Пост".to_string(), media)).as_deref(), outgoing, "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn classification_text_without_post_text() {
+        assert_eq!(PostKind::Voice.classification_text(""), "[Голосовое сообщение]");
+        assert_eq!(PostKind::Photo.classification_text(""), "");
+    }
+}
@@ -0,0 +1,372 @@
+//! Общий для всех источников пул классификации.
+//!
+//! Источники обрабатываются параллельно, а число одновременных запросов
+//! к модели ограничено `bot_settings.classify_concurrency`. Модели из `llm.models`
+//! спрашиваются по порядку, пока одна не ответит по формату.
+//!
+//! JSON ищется в ответе терпимо: ограждения ``` и текст вокруг отбрасываются.
+//! Если ответ всё равно не разобран или `status` неизвестен, модель
+//! переспрашивается с текстом ошибки, не больше `llm.max_reasks` раз.
+//! Пустой `text` допустим только для поста без текста: его подпись всё равно не публикуется.
+
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::Semaphore;
+
+use crate::{
+    llm::{ChatMessage, Image, LlmProvider, Result, Role},
+    log_warn,
+};
+
+/// Категория релевантного поста в ответе модели
+pub const RELEVANT: &str = "релевантный";
+
+/// Значения `status`, допустимые всегда. Категории маршрутов добавляются к ним
+pub const DEFAULT_CATEGORIES: [&str; 3] = [RELEVANT, "реклама", "не релевантный"];
+
+/// Текст запроса для поста из одних картинок
+const NO_TEXT: &str = "(пост без текста, только изображения)";
+
+/// Системный промпт сокращения длинного поста
+const SHORTEN_PROMPT: &str = "Сократи текст поста до {limit} символов, сохранив смысл, эмодзи и разметку \
+(HTML или Markdown). Ответ строго JSON объектом {\"text\": \"сокращённый текст\"} без пояснений.";
+
+/// Решение модели по посту
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Verdict {
+    /// `status` из ответа: одна из допустимых категорий в нижнем регистре
+    pub category: String,
+    /// Оригинальный текст или пересказ
+    pub text: String,
+}
+
+/// Ответ цепочки моделей
+#[derive(Debug, Clone)]
+pub struct Answer {
+    pub verdict: Verdict,
+    /// Модель, ответ которой принят
+    pub model: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Shortened {
+    text: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct AproveData {
+    status: String,
+    text: String,
+}
+
+pub struct Classifier {
+    /// Цепочка моделей, не пустая
+    providers: Vec<Arc<dyn LlmProvider>>,
+    permits: Semaphore,
+    max_reasks: u32,
+    /// Допустимые значения `status`, в нижнем регистре
+    categories: Vec<String>,
+}
+
+impl Classifier {
+    pub fn new(providers: Vec<Arc<dyn LlmProvider>>, concurrency: usize, max_reasks: u32) -> Self {
+        assert!(!providers.is_empty(), "at least one LLM model is required");
+        Self {
+            providers,
+            permits: Semaphore::new(concurrency.max(1)),
+            max_reasks,
+            categories: DEFAULT_CATEGORIES.map(String::from).to_vec(),
+        }
+    }
+
+    /// Разрешаем модели отвечать ещё и категориями маршрутов
+    pub fn with_categories(mut self, categories: impl IntoIterator<Item = String>) -> Self {
+        for category in categories {
+            let category = category.to_lowercase();
+            if !self.categories.contains(&category) {
+                self.categories.push(category);
+            }
+        }
+        self
+    }
+
+    /// Спрашиваем модели с системным промптом `prompt`, дождавшись свободного места в пуле.
+    /// Ошибка, таймаут или ответ не по формату после всех переспросов — переходим к следующей модели.
+    /// `Err`, если ни от одной модели не получен ответ по формату: решение стоит повторить позже,
+    /// а после всех попыток пост уходит в dead letters.
+    /// `images` — картинки поста, уходят моделям с `vision`
+    pub async fn classify(&self, prompt: &str, text: &str, images: &[Image]) -> Result<Answer> {
+        let _permit = self.permits.acquire().await?;
+
+        let text_required = !text.is_empty();
+        let text = if text.is_empty() { NO_TEXT } else { text };
+
+        let mut last_error = None;
+        let mut model = String::new();
+        'models: for provider in &self.providers {
+            model = provider.name();
+            let mut messages = vec![
+                ChatMessage::new(Role::System, prompt),
+                ChatMessage::new(Role::User, text).with_images(images.to_vec()),
+            ];
+            for attempt in 0..=self.max_reasks {
+                let answer = match provider.complete(&messages).await {
+                    Ok(answer) => answer,
+                    Err(e) => {
+                        log_warn!("Model {} failed, trying the next one: {}", model, e);
+                        last_error = Some(e);
+                        continue 'models;
+                    }
+                };
+                match parse_answer(&answer, text_required, &self.categories) {
+                    Ok(verdict) => return Ok(Answer { verdict, model }),
+                    Err(problem) => {
+                        log_warn!("Unusable answer from {} (attempt {}): {}", model, attempt + 1, problem);
+                        let reask = reask(&problem, &self.categories);
+                        last_error = Some(format!("unusable answer from {}: {}", model, problem).into());
+                        messages.push(ChatMessage::new(Role::Assistant, answer));
+                        messages.push(ChatMessage::new(Role::User, reask));
+                    }
+                }
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| format!("no usable answer from any model, last asked: {}", model).into()))
+    }
+
+    /// Просим модели по порядку сократить `text` до `limit` символов.
+    /// `Err` с последней ошибкой, если не справилась ни одна
+    pub async fn shorten(&self, text: &str, limit: usize) -> Result<String> {
+        let _permit = self.permits.acquire().await?;
+
+        let messages = [
+            ChatMessage::new(Role::System, SHORTEN_PROMPT.replace("{limit}", &limit.to_string())),
+            ChatMessage::new(Role::User, text),
+        ];
+        let mut last_error: Box<dyn std::error::Error + Send + Sync> = "no models".into();
+        for provider in &self.providers {
+            let answer = match provider.complete(&messages).await {
+                Ok(answer) => answer,
+                Err(e) => {
+                    last_error = e;
+                    continue;
+                }
+            };
+            let shortened = answer
+                .match_indices('{')
+                .filter_map(|(start, _)| balanced_object(&answer[start..]))
+                .find_map(|candidate| serde_json::from_str::<Shortened>(candidate).ok());
+            match shortened {
+                Some(shortened) if !shortened.text.trim().is_empty() => return Ok(shortened.text),
+                _ => last_error = format!("unusable answer from {}", provider.name()).into(),
+            }
+        }
+        Err(last_error)
+    }
+}
+
+/// Разбираем ответ модели. Пробуем каждый сбалансированный `{...}` по порядку,
+/// ошибка — от первого кандидата, её и возвращаем модели.
+/// `text_required` — у поста есть текст, и релевантный ответ без текста не принимаем.
+/// `categories` — допустимые значения `status`
+fn parse_answer(answer: &str, text_required: bool, categories: &[String]) -> std::result::Result<Verdict, String> {
+    let mut error = None;
+    for (start, _) in answer.match_indices('{') {
+        let Some(candidate) = balanced_object(&answer[start..]) else {
+            continue;
+        };
+        match serde_json::from_str::<AproveData>(candidate) {
+            Ok(data) => return validate(data, text_required, categories),
+            Err(e) => {
+                error.get_or_insert_with(|| format!("invalid JSON: {}", e));
+            }
+        }
+    }
+    Err(error.unwrap_or_else(|| "no JSON object in the answer".to_string()))
+}
+
+/// Объект от первой `{` до парной `}` с учётом строк и экранирования
+fn balanced_object(text: &str) -> Option<&str> {
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+    for (offset, c) in text.char_indices() {
+        if in_string {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_string = false;
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(&text[..offset + 1]);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+fn validate(data: AproveData, text_required: bool, categories: &[String]) -> std::result::Result<Verdict, String> {
+    let category = data.status.trim().to_lowercase();
+    if !categories.contains(&category) {
+        return Err(format!(
+            "unknown status \"{}\", expected one of: {}",
+            data.status,
+            categories.join(", ")
+        ));
+    }
+    if category == RELEVANT && text_required && data.text.trim().is_empty() {
+        return Err("text is empty for a relevant post".to_string());
+    }
+    Ok(Verdict {
+        category,
+        text: data.text,
+    })
+}
+
+/// Сообщение модели с ошибкой разбора её ответа
+fn reask(problem: &str, categories: &[String]) -> String {
+    format!(
+        "Ответ не удалось разобрать: {}. Ответь ещё раз только JSON объектом {{\"status\": \"{}\", \"text\": \"...\"}} без пояснений и форматирования.",
+        problem,
+        categories.join("\" | \"")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::fake::FakeProvider;
+
+    const RELEVANT_ANSWER: &str = r#"{"status": "релевантный", "text": "Вышла новая модель"}"#;
+
+    fn defaults() -> Vec<String> {
+        DEFAULT_CATEGORIES.map(String::from).to_vec()
+    }
+
+    #[test]
+    fn parse_answer_extracts_json() {
+        let cases = [
+            ("plain", RELEVANT_ANSWER.to_string()),
+            ("fenced", format!("```json\n{}\n```", RELEVANT_ANSWER)),
+            ("prose around", format!("Вот ответ: {} Надеюсь, помог!", RELEVANT_ANSWER)),
+            ("broken candidate first", format!("{{status}} {}", RELEVANT_ANSWER)),
+            ("status case and spaces", r#"{"status": " Релевантный ", "text": "Вышла новая модель"}"#.to_string()),
+        ];
+        for (name, answer) in cases {
+            let verdict = parse_answer(&answer, true, &defaults()).unwrap_or_else(|e| panic!("{}: {}", name, e));
+            assert_eq!(verdict.category, RELEVANT, "{}", name);
+            assert_eq!(verdict.text, "Вышла новая модель", "{}", name);
+        }
+    }
+
+    #[test]
+    fn parse_answer_keeps_braces_inside_text() {
+        let answer = r#"{"status": "релевантный", "text": "Конфиг {\"a\": {\"b\": 1}} и скобка }"} PS"#;
+        let verdict = parse_answer(answer, true, &defaults()).unwrap();
+        assert_eq!(verdict.text, r#"Конфиг {"a": {"b": 1}} и скобка }"#);
+    }
+
+    #[test]
+    fn parse_answer_rejects_bad_values() {
+        let cases = [
+            ("no json", "Пост релевантный", "no JSON object"),
+            ("unknown status", r#"{"status": "важный", "text": "..."}"#, "unknown status"),
+            ("status is a number", r#"{"status": 1, "text": "..."}"#, "invalid JSON"),
+            ("missing text", r#"{"status": "реклама"}"#, "invalid JSON"),
+            ("unbalanced", r#"{"status": "реклама", "text": "...""#, "no JSON object"),
+            ("relevant without text", r#"{"status": "релевантный", "text": " "}"#, "text is empty"),
+        ];
+        for (name, answer, problem) in cases {
+            let error = parse_answer(answer, true, &defaults()).expect_err(name);
+            assert!(error.contains(problem), "{}: {}", name, error);
+        }
+    }
+
+    #[test]
+    fn empty_text_is_allowed_for_posts_without_text() {
+        let answer = r#"{"status": "релевантный", "text": ""}"#;
+        assert!(parse_answer(answer, true, &defaults()).is_err());
+        assert_eq!(parse_answer(answer, false, &defaults()).unwrap().text, "");
+        // Нерелевантному посту текст не нужен никогда
+        assert!(parse_answer(r#"{"status": "реклама", "text": ""}"#, true, &defaults()).is_ok());
+    }
+
+    #[test]
+    fn balanced_object_boundaries() {
+        let cases = [
+            ("{} tail", Some("{}")),
+            ("{\"a\": {\"b\": {}}} tail", Some("{\"a\": {\"b\": {}}}")),
+            ("{\"a\": \"}\"} tail", Some("{\"a\": \"}\"}")),
+            ("{\"a\": \"\\\"}\"} tail", Some("{\"a\": \"\\\"}\"}")),
+            ("{\"a\": {}", None),
+            ("{\"a\": \"}", None),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(balanced_object(text), expected, "{:?}", text);
+        }
+    }
+
+    #[tokio::test]
+    async fn reasks_and_falls_back_to_next_model() {
+        let broken = FakeProvider::new("broken", |_| Err("connection refused".into()));
+        let sloppy = FakeProvider::new("sloppy", |message| {
+            let answer = if message.starts_with("Ответ не удалось разобрать") {
+                RELEVANT_ANSWER
+            } else {
+                "релевантный"
+            };
+            Ok(answer.to_string())
+        });
+        let classifier = Classifier::new(vec![broken.clone(), sloppy.clone()], 1, 2);
+
+        let answer = classifier.classify("prompt", "Пост", &[]).await.unwrap();
+        assert_eq!(answer.model, "sloppy");
+        assert_eq!(answer.verdict.category, RELEVANT);
+        assert_eq!((broken.calls(), sloppy.calls()), (1, 2));
+    }
+
+    #[tokio::test]
+    async fn unparseable_answers_are_an_error() {
+        let model = FakeProvider::new("model", |_| Ok("не знаю".to_string()));
+        let classifier = Classifier::new(vec![model.clone()], 1, 2);
+
+        let error = classifier.classify("prompt", "Пост", &[]).await.unwrap_err();
+        assert_eq!(error.to_string(), "unusable answer from model: no JSON object in the answer");
+        assert_eq!(model.calls(), 3);
+    }
+
+    #[tokio::test]
+    async fn route_categories_are_accepted() {
+        let model = FakeProvider::new("model", |_| Ok(r#"{"status": "Вакансия", "text": "Ищем"}"#.to_string()));
+        let classifier = Classifier::new(vec![model.clone()], 1, 0);
+        assert!(classifier.classify("prompt", "Пост", &[]).await.is_err());
+
+        let classifier = Classifier::new(vec![model], 1, 0).with_categories(["Вакансия".to_string()]);
+        let answer = classifier.classify("prompt", "Пост", &[]).await.unwrap();
+        assert_eq!(answer.verdict.category, "вакансия");
+    }
+
+    #[tokio::test]
+    async fn post_without_text_is_described_to_the_model() {
+        let model = FakeProvider::new("model", |message| {
+            assert_eq!(message, NO_TEXT);
+            Ok(r#"{"status": "релевантный", "text": ""}"#.to_string())
+        });
+        let classifier = Classifier::new(vec![model], 1, 0);
+
+        let answer = classifier.classify("prompt", "", &[]).await.unwrap();
+        assert_eq!(answer.verdict.text, "");
+    }
+}
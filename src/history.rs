@@ -0,0 +1,249 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::classifier::RELEVANT;
+
+/// Сколько обработанных id держать на источник, если не задано иное
+pub const DEFAULT_RETENTION: usize = 5000;
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct History {
+    pub messages: HashMap<i64, BTreeSet<i32>>, // chat_id -> обработанные message_id
+    /// Последний обработанный id в каждом источнике, с него продолжаем после простоя
+    #[serde(default)]
+    pub high_water: HashMap<i64, i32>, // chat_id -> message_id
+    /// Куда был опубликован каждый пост источника
+    #[serde(default)]
+    pub mapping: HashMap<i64, BTreeMap<i32, Vec<TargetRef>>>, // chat_id -> message_id -> копии
+}
+
+/// Копия поста в target канале
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TargetRef {
+    pub chat_id: i64,
+    /// Пусто — копия удалена, потому что после правки пост перестал подходить
+    pub message_ids: Vec<i32>,
+    /// Unix time правки оригинала, с которой опубликована копия. `None` — оригинал не правился
+    #[serde(default)]
+    pub edit_date: Option<i64>,
+}
+
+/// Решение классификатора по посту
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Decision {
+    /// Категория из ответа модели, `None` — ответ не разобран (записи старых версий)
+    pub category: Option<String>,
+    /// Оригинальный текст или пересказ
+    pub text: Option<String>,
+    /// Какая модель приняла решение
+    pub model: String,
+}
+
+/// Сколько последних обработанных id хранить для каждого источника
+#[derive(Debug, Clone)]
+pub struct Retention {
+    pub default: usize,
+    pub per_chat: HashMap<i64, usize>,
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Self {
+            default: DEFAULT_RETENTION,
+            per_chat: HashMap::new(),
+        }
+    }
+}
+
+impl Retention {
+    pub fn limit(&self, chat_id: i64) -> usize {
+        self.per_chat.get(&chat_id).copied().unwrap_or(self.default)
+    }
+}
+
+impl Decision {
+    pub fn is_relevant(&self) -> bool {
+        self.category.as_deref() == Some(RELEVANT)
+    }
+}
+
+impl History {
+    pub fn is_processed(&self, chat_id: i64, id: i32) -> bool {
+        self.messages.get(&chat_id).is_some_and(|ids| ids.contains(&id))
+    }
+
+    pub fn mark_processed(&mut self, chat_id: i64, ids: &[i32]) {
+        self.messages.entry(chat_id).or_default().extend(ids);
+    }
+
+    /// Поднимаем отметку источника, возвращаем `true`, если она изменилась
+    pub fn raise_high_water(&mut self, chat_id: i64, id: i32) -> bool {
+        let mark = self.high_water.entry(chat_id).or_default();
+        if id > *mark {
+            *mark = id;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Разбиваем историю по источникам, чтобы у каждого было своё состояние
+    pub fn split(mut self) -> HashMap<i64, History> {
+        let mut chats: HashMap<i64, History> = HashMap::new();
+        for (chat_id, ids) in self.messages.drain() {
+            chats.entry(chat_id).or_default().messages.insert(chat_id, ids);
+        }
+        for (chat_id, mark) in self.high_water.drain() {
+            chats.entry(chat_id).or_default().high_water.insert(chat_id, mark);
+        }
+        for (chat_id, mapping) in self.mapping.drain() {
+            chats.entry(chat_id).or_default().mapping.insert(chat_id, mapping);
+        }
+        chats
+    }
+
+    /// Запоминаем копию поста. Прежняя копия в том же target заменяется
+    pub fn add_mapping(&mut self, chat_id: i64, id: i32, target: TargetRef) {
+        let targets = self.mapping.entry(chat_id).or_default().entry(id).or_default();
+        match targets.iter_mut().find(|copy| copy.chat_id == target.chat_id) {
+            Some(copy) => *copy = target,
+            None => targets.push(target),
+        }
+    }
+
+    /// Забываем копии поста, удалённого в источнике
+    pub fn remove_mapping(&mut self, chat_id: i64, id: i32) {
+        if let Some(mapping) = self.mapping.get_mut(&chat_id) {
+            mapping.remove(&id);
+        }
+    }
+
+    /// Копии поста источника
+    pub fn copies(&self, chat_id: i64, id: i32) -> &[TargetRef] {
+        self.mapping
+            .get(&chat_id)
+            .and_then(|mapping| mapping.get(&id))
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// Последние `limit` опубликованных постов источника
+    pub fn recent_published(&self, chat_id: i64, limit: usize) -> Vec<i32> {
+        let Some(mapping) = self.mapping.get(&chat_id) else {
+            return Vec::new();
+        };
+        let mut ids: Vec<i32> = mapping.keys().rev().take(limit).copied().collect();
+        ids.reverse();
+        ids
+    }
+
+    /// Оставляем по каждому источнику только последние id по `retention`.
+    /// Старые посты ниже отметки источника, их уже не догоняем, так что забыть их безопасно
+    pub fn trim(&mut self, retention: &Retention) {
+        for (chat_id, ids) in self.messages.iter_mut() {
+            let limit = retention.limit(*chat_id);
+            while ids.len() > limit {
+                ids.pop_first();
+            }
+
+            if let (Some(&oldest), Some(mapping)) = (ids.first(), self.mapping.get_mut(chat_id)) {
+                *mapping = mapping.split_off(&oldest);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn copy(chat_id: i64, message_ids: &[i32]) -> TargetRef {
+        TargetRef {
+            chat_id,
+            message_ids: message_ids.to_vec(),
+            edit_date: None,
+        }
+    }
+
+    #[test]
+    fn trim_keeps_latest_ids_per_chat() {
+        let mut history = History::default();
+        history.mark_processed(1, &[1, 2, 3, 4, 5]);
+        history.mark_processed(2, &[10, 11, 12]);
+        let retention = Retention {
+            default: 3,
+            per_chat: HashMap::from([(2, 1)]),
+        };
+
+        history.trim(&retention);
+
+        assert_eq!(history.messages[&1], BTreeSet::from([3, 4, 5]));
+        assert_eq!(history.messages[&2], BTreeSet::from([12]));
+        assert!(!history.is_processed(1, 2));
+        assert!(history.is_processed(1, 3));
+    }
+
+    #[test]
+    fn trim_drops_mapping_below_oldest_kept_id() {
+        let mut history = History::default();
+        history.mark_processed(1, &[1, 2, 3]);
+        history.add_mapping(1, 1, copy(100, &[50]));
+        history.add_mapping(1, 3, copy(100, &[51]));
+
+        history.trim(&Retention {
+            default: 2,
+            per_chat: HashMap::new(),
+        });
+
+        assert!(history.copies(1, 1).is_empty());
+        assert_eq!(history.copies(1, 3), [copy(100, &[51])]);
+    }
+
+    #[test]
+    fn mapping_is_replaced_per_target() {
+        let mut history = History::default();
+        history.add_mapping(1, 7, copy(100, &[50]));
+        history.add_mapping(1, 7, copy(200, &[60]));
+        history.add_mapping(1, 7, copy(100, &[52, 53]));
+        assert_eq!(history.copies(1, 7), [copy(100, &[52, 53]), copy(200, &[60])]);
+
+        history.remove_mapping(1, 7);
+        assert!(history.copies(1, 7).is_empty());
+    }
+
+    #[test]
+    fn recent_published_returns_latest_in_order() {
+        let mut history = History::default();
+        for id in [3, 1, 5, 4] {
+            history.add_mapping(1, id, copy(100, &[id + 100]));
+        }
+        assert_eq!(history.recent_published(1, 2), [4, 5]);
+        assert_eq!(history.recent_published(1, 10), [1, 3, 4, 5]);
+        assert!(history.recent_published(2, 10).is_empty());
+    }
+
+    #[test]
+    fn high_water_only_rises() {
+        let mut history = History::default();
+        assert!(history.raise_high_water(1, 10));
+        assert!(!history.raise_high_water(1, 5));
+        assert!(!history.raise_high_water(1, 10));
+        assert_eq!(history.high_water[&1], 10);
+    }
+
+    #[test]
+    fn split_separates_chats() {
+        let mut history = History::default();
+        history.mark_processed(1, &[1]);
+        history.mark_processed(2, &[2]);
+        history.raise_high_water(2, 2);
+        history.add_mapping(1, 1, copy(100, &[50]));
+
+        let chats = history.split();
+        assert!(chats[&1].is_processed(1, 1));
+        assert!(!chats[&1].is_processed(2, 2));
+        assert_eq!(chats[&1].copies(1, 1), [copy(100, &[50])]);
+        assert_eq!(chats[&2].high_water[&2], 2);
+        assert!(chats[&2].mapping.is_empty());
+    }
+}
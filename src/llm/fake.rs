@@ -0,0 +1,72 @@
+//! Модель для тестов: отвечает функцией от последнего сообщения пользователя
+//! и запоминает, сколько раз её спросили.
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use super::{BoxFuture, ChatMessage, LlmProvider, Result, Role};
+
+type Answer = Box<dyn Fn(&str) -> Result<String> + Send + Sync>;
+
+pub struct FakeProvider {
+    name: String,
+    answer: Answer,
+    calls: AtomicUsize,
+    /// Подстрока сообщения -> задержка ответа
+    delays: Mutex<Vec<(String, Duration)>>,
+}
+
+impl FakeProvider {
+    pub fn new(name: &str, answer: impl Fn(&str) -> Result<String> + Send + Sync + 'static) -> Arc<Self> {
+        Arc::new(Self {
+            name: name.to_string(),
+            answer: Box::new(answer),
+            calls: AtomicUsize::new(0),
+            delays: Mutex::default(),
+        })
+    }
+
+    /// На сообщения, содержащие `needle`, модель отвечает через `delay`
+    pub fn delay_on(&self, needle: &str, delay: Duration) {
+        self.delays.lock().unwrap().push((needle.to_string(), delay));
+    }
+
+    /// Сколько раз модель спросили
+    pub fn calls(&self) -> usize {
+        self.calls.load(Ordering::SeqCst)
+    }
+}
+
+impl LlmProvider for FakeProvider {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn complete<'a>(&'a self, messages: &'a [ChatMessage]) -> BoxFuture<'a, Result<String>> {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        let last = messages
+            .iter()
+            .rev()
+            .find(|message| message.role == Role::User)
+            .map_or("", |message| message.content.as_str());
+        let answer = (self.answer)(last);
+        let delay = self
+            .delays
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(needle, _)| last.contains(needle.as_str()))
+            .map(|&(_, delay)| delay);
+        Box::pin(async move {
+            if let Some(delay) = delay {
+                tokio::time::sleep(delay).await;
+            }
+            answer
+        })
+    }
+}
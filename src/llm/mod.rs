@@ -0,0 +1,149 @@
+//! LLM провайдеры для классификатора.
+//!
+//! Какой провайдер использовать, задаётся секцией `llm` в `config.json`:
+//! Mistral, любой OpenAI-совместимый сервер (vLLM, llama.cpp server, LM Studio) или Ollama.
+//! Моделей может быть несколько: классификатор спрашивает их по порядку.
+
+use std::{error::Error, future::Future, pin::Pin, sync::Arc, time::Duration};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use crate::config::{LlmConfig, LlmProviderKind, ModelConfig};
+
+#[cfg(test)]
+pub mod fake;
+pub mod mistral;
+pub mod ollama;
+pub mod openai;
+
+pub type Result<T> = std::result::Result<T, Box<dyn Error + Send + Sync>>;
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    System,
+    User,
+    Assistant,
+}
+
+impl Role {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::System => "system",
+            Role::User => "user",
+            Role::Assistant => "assistant",
+        }
+    }
+}
+
+/// Картинка для мультимодальной модели
+#[derive(Debug, Clone)]
+pub struct Image {
+    /// Например `image/jpeg`
+    pub mime: String,
+    pub data: Arc<[u8]>,
+}
+
+impl Image {
+    pub fn base64(&self) -> String {
+        STANDARD.encode(&self.data)
+    }
+
+    /// `data:` URL для `image_url`
+    pub fn data_url(&self) -> String {
+        format!("data:{};base64,{}", self.mime, self.base64())
+    }
+}
+
+/// Сообщение диалога с моделью
+#[derive(Debug, Clone)]
+pub struct ChatMessage {
+    pub role: Role,
+    pub content: String,
+    /// Картинки после текста, только у сообщений пользователя
+    pub images: Vec<Image>,
+}
+
+impl ChatMessage {
+    pub fn new(role: Role, content: impl Into<String>) -> Self {
+        Self {
+            role,
+            content: content.into(),
+            images: Vec::new(),
+        }
+    }
+
+    pub fn with_images(mut self, images: Vec<Image>) -> Self {
+        self.images = images;
+        self
+    }
+}
+
+/// Провайдер чат-модели. Модель и её параметры задаются при создании.
+pub trait LlmProvider: Send + Sync {
+    /// Имя для логов, например `mistral:pixtral-large-latest`
+    fn name(&self) -> String;
+
+    /// Отправляем диалог, получаем текст ответа модели
+    fn complete<'a>(&'a self, messages: &'a [ChatMessage]) -> BoxFuture<'a, Result<String>>;
+}
+
+/// Параметры генерации одной модели
+#[derive(Debug, Clone)]
+pub struct Sampling {
+    pub temperature: f32,
+    /// `None` — ограничение сервера
+    pub max_tokens: Option<u32>,
+    pub top_p: Option<f32>,
+    pub seed: Option<u64>,
+    /// Сколько ждать ответа, включая соединение
+    pub timeout: Duration,
+    /// Просить сервер отвечать только JSON объектом
+    pub json_mode: bool,
+    /// Модель принимает картинки. Иначе они не отправляются
+    pub vision: bool,
+}
+
+impl From<&ModelConfig> for Sampling {
+    fn from(config: &ModelConfig) -> Self {
+        Self {
+            temperature: config.temperature,
+            max_tokens: config.max_tokens,
+            top_p: config.top_p,
+            seed: config.seed,
+            timeout: Duration::from_secs(config.timeout_secs),
+            json_mode: config.json_mode,
+            vision: config.vision,
+        }
+    }
+}
+
+/// Создаём цепочку провайдеров по конфигу, в порядке `llm.models`.
+/// `mistral_token` из `main_config` используется, если у модели Mistral не указан `api_key`.
+pub fn build_providers(config: &LlmConfig, mistral_token: &str) -> Vec<Arc<dyn LlmProvider>> {
+    config
+        .models()
+        .iter()
+        .map(|model| build_provider(model, mistral_token))
+        .collect()
+}
+
+fn build_provider(config: &ModelConfig, mistral_token: &str) -> Arc<dyn LlmProvider> {
+    let sampling = Sampling::from(config);
+    match config.provider.unwrap_or_default() {
+        LlmProviderKind::Mistral => {
+            let api_key = config.api_key.clone().unwrap_or_else(|| mistral_token.to_string());
+            let base_url = config.base_url.as_deref().unwrap_or(mistral::DEFAULT_BASE_URL);
+            Arc::new(mistral::MistralClient::new(base_url, &api_key, &config.model, sampling))
+        }
+        LlmProviderKind::OpenAi => {
+            let base_url = config.base_url.as_deref().unwrap_or(openai::DEFAULT_BASE_URL);
+            Arc::new(openai::OpenAiClient::new(base_url, config.api_key.as_deref(), &config.model, sampling))
+        }
+        LlmProviderKind::Ollama => {
+            let base_url = config.base_url.as_deref().unwrap_or(ollama::DEFAULT_BASE_URL);
+            Arc::new(ollama::OllamaClient::new(base_url, &config.model, sampling))
+        }
+    }
+}
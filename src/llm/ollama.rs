@@ -0,0 +1,229 @@
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+
+use super::{BoxFuture, ChatMessage, LlmProvider, Result, Sampling};
+use crate::log_debug;
+
+pub const DEFAULT_BASE_URL: &str = "http://localhost:11434";
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+struct Message {
+    role: String,
+    content: String,
+    /// Картинки в base64
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    images: Vec<String>,
+}
+
+fn request_message(message: &ChatMessage, vision: bool) -> Message {
+    let images = if vision {
+        message.images.iter().map(|image| image.base64()).collect()
+    } else {
+        Vec::new()
+    };
+    Message {
+        role: message.role.as_str().to_string(),
+        content: message.content.clone(),
+        images,
+    }
+}
+
+#[derive(Serialize)]
+struct Options {
+    temperature: f32,
+    /// Аналог `max_tokens`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    num_predict: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct ChatRequest {
+    model: String,
+    messages: Vec<Message>,
+    /// Без стрима Ollama отдаёт ответ одним JSON объектом
+    stream: bool,
+    /// `"json"` включает JSON режим
+    #[serde(skip_serializing_if = "Option::is_none")]
+    format: Option<&'static str>,
+    options: Options,
+}
+
+#[derive(Deserialize, Debug)]
+struct ChatResponse {
+    message: Message,
+}
+
+/// Клиент для Ollama `/api/chat`
+pub struct OllamaClient {
+    client: Client,
+    api_url: String,
+    model: String,
+    sampling: Sampling,
+}
+
+impl OllamaClient {
+    pub fn new(base_url: &str, model: &str, sampling: Sampling) -> Self {
+        OllamaClient {
+            client: Client::new(),
+            api_url: format!("{}/api/chat", base_url.trim_end_matches('/')),
+            model: model.to_string(),
+            sampling,
+        }
+    }
+
+    fn request(&self, messages: &[ChatMessage]) -> ChatRequest {
+        ChatRequest {
+            model: self.model.clone(),
+            messages: messages
+                .iter()
+                .map(|message| request_message(message, self.sampling.vision))
+                .collect(),
+            stream: false,
+            format: self.sampling.json_mode.then_some("json"),
+            options: Options {
+                temperature: self.sampling.temperature,
+                num_predict: self.sampling.max_tokens,
+                top_p: self.sampling.top_p,
+                seed: self.sampling.seed,
+            },
+        }
+    }
+
+    async fn get_response(&self, messages: &[ChatMessage]) -> Result<String> {
+        log_debug!("Отправляем ИИ запрос (ollama): {:#?}", messages.last().map(|message| &message.content));
+
+        let request_body = self.request(messages);
+
+        let response = self
+            .client
+            .post(&self.api_url)
+            .timeout(self.sampling.timeout)
+            .json(&request_body)
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(format!("Err: {}", response.status()).into());
+        }
+
+        let data = response.json::<ChatResponse>().await?;
+        log_debug!("DATA: {:#?}", data);
+        Ok(data.message.content)
+    }
+}
+
+impl LlmProvider for OllamaClient {
+    fn name(&self) -> String {
+        format!("ollama:{}", self.model)
+    }
+
+    fn complete<'a>(&'a self, messages: &'a [ChatMessage]) -> BoxFuture<'a, Result<String>> {
+        Box::pin(self.get_response(messages))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        config::ModelConfig,
+        llm::{Image, Role},
+    };
+
+    /// Параметры по умолчанию с точно представимой температурой
+    fn sampling(config: ModelConfig) -> Sampling {
+        Sampling::from(&ModelConfig {
+            temperature: 0.5,
+            ..config
+        })
+    }
+
+    /// Системный промпт и пост с картинкой
+    fn messages() -> Vec<ChatMessage> {
+        let image = Image {
+            mime: "image/png".to_string(),
+            data: Arc::from(&b"png"[..]),
+        };
+        vec![
+            ChatMessage::new(Role::System, "prompt"),
+            ChatMessage::new(Role::User, "post").with_images(vec![image]),
+        ]
+    }
+
+    /// Без JSON режима, картинок и необязательных параметров
+    fn minimal() -> ModelConfig {
+        ModelConfig {
+            json_mode: false,
+            vision: false,
+            ..Default::default()
+        }
+    }
+
+    /// Все необязательные параметры заданы
+    fn full() -> ModelConfig {
+        ModelConfig {
+            max_tokens: Some(512),
+            top_p: Some(0.25),
+            seed: Some(42),
+            ..Default::default()
+        }
+    }
+
+    fn client(config: ModelConfig) -> OllamaClient {
+        OllamaClient::new(DEFAULT_BASE_URL, "llava", sampling(config))
+    }
+
+    #[test]
+    fn request_omits_unset_parameters() {
+        let request = serde_json::to_value(client(minimal()).request(&messages())).unwrap();
+        let expected = json!({
+            "model": "llava",
+            "messages": [
+                { "role": "system", "content": "prompt" },
+                { "role": "user", "content": "post" },
+            ],
+            "stream": false,
+            "options": { "temperature": 0.5 },
+        });
+        assert_eq!(request, expected);
+    }
+
+    #[test]
+    fn request_with_parameters_json_mode_and_images() {
+        let request = serde_json::to_value(client(full()).request(&messages())).unwrap();
+        let expected = json!({
+            "model": "llava",
+            "messages": [
+                { "role": "system", "content": "prompt" },
+                { "role": "user", "content": "post", "images": ["cG5n"] },
+            ],
+            "stream": false,
+            "format": "json",
+            "options": { "temperature": 0.5, "num_predict": 512, "top_p": 0.25, "seed": 42 },
+        });
+        assert_eq!(request, expected);
+    }
+
+    #[test]
+    fn response_is_parsed() {
+        let body = json!({
+            "model": "llava",
+            "created_at": "2024-01-01T00:00:00Z",
+            "message": { "role": "assistant", "content": "ответ" },
+            "done_reason": "stop",
+            "done": true,
+            "total_duration": 1000,
+            "eval_count": 2,
+        });
+        let response: ChatResponse = serde_json::from_value(body).unwrap();
+        assert_eq!(response.message.content, "ответ");
+        assert!(response.message.images.is_empty());
+    }
+}
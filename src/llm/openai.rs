@@ -0,0 +1,278 @@
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+
+use super::{BoxFuture, ChatMessage, LlmProvider, Result, Sampling};
+use crate::log_debug;
+
+/// vLLM и llama.cpp server по умолчанию слушают 8000/8080, LM Studio — 1234.
+/// Берём vLLM, остальное задаётся через `base_url`.
+pub const DEFAULT_BASE_URL: &str = "http://localhost:8000";
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+struct Message {
+    role: String,
+    content: String,
+}
+
+#[derive(Serialize)]
+struct RequestMessage {
+    role: &'static str,
+    content: Content,
+}
+
+/// Текст строкой, с картинками — списком частей
+#[derive(Serialize)]
+#[serde(untagged)]
+enum Content {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ContentPart {
+    Text { text: String },
+    ImageUrl { image_url: ImageUrl },
+}
+
+#[derive(Serialize)]
+struct ImageUrl {
+    url: String,
+}
+
+fn request_message(message: &ChatMessage, vision: bool) -> RequestMessage {
+    let content = if vision && !message.images.is_empty() {
+        let mut parts = vec![ContentPart::Text {
+            text: message.content.clone(),
+        }];
+        parts.extend(message.images.iter().map(|image| ContentPart::ImageUrl {
+            image_url: ImageUrl { url: image.data_url() },
+        }));
+        Content::Parts(parts)
+    } else {
+        Content::Text(message.content.clone())
+    };
+    RequestMessage {
+        role: message.role.as_str(),
+        content,
+    }
+}
+
+#[derive(Serialize)]
+struct ResponseFormat {
+    #[serde(rename = "type")]
+    kind: &'static str,
+}
+
+#[derive(Serialize)]
+struct CompletionRequest {
+    model: String,
+    temperature: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_format: Option<ResponseFormat>,
+    messages: Vec<RequestMessage>,
+}
+
+#[derive(Deserialize, Debug)]
+struct CompletionResponse {
+    choices: Vec<Choice>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Choice {
+    message: Message,
+}
+
+/// Клиент для любого сервера с OpenAI-совместимым `/v1/chat/completions`
+pub struct OpenAiClient {
+    client: Client,
+    api_url: String,
+    api_key: Option<String>,
+    model: String,
+    sampling: Sampling,
+}
+
+impl OpenAiClient {
+    pub fn new(base_url: &str, api_key: Option<&str>, model: &str, sampling: Sampling) -> Self {
+        OpenAiClient {
+            client: Client::new(),
+            api_url: format!("{}/v1/chat/completions", base_url.trim_end_matches('/')),
+            api_key: api_key.map(str::to_string),
+            model: model.to_string(),
+            sampling,
+        }
+    }
+
+    fn request(&self, messages: &[ChatMessage]) -> CompletionRequest {
+        CompletionRequest {
+            model: self.model.clone(),
+            temperature: self.sampling.temperature,
+            max_tokens: self.sampling.max_tokens,
+            top_p: self.sampling.top_p,
+            seed: self.sampling.seed,
+            response_format: self.sampling.json_mode.then_some(ResponseFormat { kind: "json_object" }),
+            messages: messages
+                .iter()
+                .map(|message| request_message(message, self.sampling.vision))
+                .collect(),
+        }
+    }
+
+    async fn get_response(&self, messages: &[ChatMessage]) -> Result<String> {
+        log_debug!(
+            "Отправляем ИИ запрос ({}): {:#?}",
+            self.api_url,
+            messages.last().map(|message| &message.content)
+        );
+
+        let request_body = self.request(messages);
+
+        let mut request = self.client.post(&self.api_url).timeout(self.sampling.timeout).json(&request_body);
+        // Локальные серверы обычно работают без ключа
+        if let Some(api_key) = &self.api_key {
+            request = request.bearer_auth(api_key);
+        }
+
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            return Err(format!("Err: {}", response.status()).into());
+        }
+
+        let data = response.json::<CompletionResponse>().await?;
+        log_debug!("DATA: {:#?}", data);
+        data.choices
+            .into_iter()
+            .next()
+            .map(|choice| choice.message.content)
+            .ok_or_else(|| "Server returned no choices".into())
+    }
+}
+
+impl LlmProvider for OpenAiClient {
+    fn name(&self) -> String {
+        format!("openai:{}", self.model)
+    }
+
+    fn complete<'a>(&'a self, messages: &'a [ChatMessage]) -> BoxFuture<'a, Result<String>> {
+        Box::pin(self.get_response(messages))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        config::ModelConfig,
+        llm::{Image, Role},
+    };
+
+    /// Параметры по умолчанию с точно представимой температурой
+    fn sampling(config: ModelConfig) -> Sampling {
+        Sampling::from(&ModelConfig {
+            temperature: 0.5,
+            ..config
+        })
+    }
+
+    /// Системный промпт и пост с картинкой
+    fn messages() -> Vec<ChatMessage> {
+        let image = Image {
+            mime: "image/png".to_string(),
+            data: Arc::from(&b"png"[..]),
+        };
+        vec![
+            ChatMessage::new(Role::System, "prompt"),
+            ChatMessage::new(Role::User, "post").with_images(vec![image]),
+        ]
+    }
+
+    /// Без JSON режима, картинок и необязательных параметров
+    fn minimal() -> ModelConfig {
+        ModelConfig {
+            json_mode: false,
+            vision: false,
+            ..Default::default()
+        }
+    }
+
+    /// Все необязательные параметры заданы
+    fn full() -> ModelConfig {
+        ModelConfig {
+            max_tokens: Some(512),
+            top_p: Some(0.25),
+            seed: Some(42),
+            ..Default::default()
+        }
+    }
+
+    fn client(config: ModelConfig) -> OpenAiClient {
+        OpenAiClient::new(DEFAULT_BASE_URL, None, "qwen", sampling(config))
+    }
+
+    #[test]
+    fn request_omits_unset_parameters() {
+        let request = serde_json::to_value(client(minimal()).request(&messages())).unwrap();
+        let expected = json!({
+            "model": "qwen",
+            "temperature": 0.5,
+            "messages": [
+                { "role": "system", "content": "prompt" },
+                { "role": "user", "content": "post" },
+            ],
+        });
+        assert_eq!(request, expected);
+    }
+
+    #[test]
+    fn request_with_parameters_json_mode_and_images() {
+        let request = serde_json::to_value(client(full()).request(&messages())).unwrap();
+        let expected = json!({
+            "model": "qwen",
+            "temperature": 0.5,
+            "max_tokens": 512,
+            "top_p": 0.25,
+            "seed": 42,
+            "response_format": { "type": "json_object" },
+            "messages": [
+                { "role": "system", "content": "prompt" },
+                {
+                    "role": "user",
+                    "content": [
+                        { "type": "text", "text": "post" },
+                        { "type": "image_url", "image_url": { "url": "data:image/png;base64,cG5n" } },
+                    ],
+                },
+            ],
+        });
+        assert_eq!(request, expected);
+    }
+
+    #[test]
+    fn response_is_parsed() {
+        let body = json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion",
+            "created": 1_700_000_000,
+            "model": "qwen",
+            "choices": [{
+                "index": 0,
+                "message": { "role": "assistant", "content": "ответ", "reasoning_content": null },
+                "logprobs": null,
+                "finish_reason": "stop",
+            }],
+            "usage": { "prompt_tokens": 10, "completion_tokens": 2, "total_tokens": 12 },
+        });
+        let response: CompletionResponse = serde_json::from_value(body).unwrap();
+        assert_eq!(response.choices[0].message.content, "ответ");
+    }
+}
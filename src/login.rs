@@ -37,14 +37,14 @@ impl ReconnectionPolicy for MyPolicy {
         println!("Переподключаемся...");
 
         // Вычисляем время ожидания с ограничением
-        let duration = Duration::from_millis((2u64.pow(attempts as u32)).min(30000 as u64));
+        let duration = Duration::from_millis((2u64.pow(attempts as u32)).min(30000));
         ControlFlow::Continue(duration)
     }
 }
 
 pub async fn login(api_id: i32, api_hash: String, session_file: &str) -> Client {
     let config = Config {
-        session: Session::load_file_or_create("session.session").unwrap(),
+        session: Session::load_file_or_create(session_file).unwrap(),
         api_id,
         api_hash,
         params: InitParams {
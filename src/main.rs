@@ -1,271 +1,219 @@
-use std::{collections::HashMap, sync::Arc, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::Duration,
+};
 
 use dotenv::dotenv;
-use grammers_client::{
-    types::{Chat, Message},
-    Client, InputMedia, InputMessage, InvocationError,
+use tokio::time::sleep;
+
+use crate::{
+    classifier::Classifier,
+    history::Retention,
+    pipeline::Pipeline,
+    prompt::{PromptProfile, Prompts},
+    routing::Route,
+    storage::open_storage,
+    supervisor::Supervisor,
+    transport::{telegram::TelegramTransport, ChatTransport},
 };
 
-use serde::{Deserialize, Serialize};
-use tokio::{sync::Mutex, time::sleep};
-
-use crate::handler::generate;
-
 mod login;
+mod bot;
+mod classifier;
 mod config;
-mod handler;
-mod mistral;
+mod handlers;
+mod history;
+mod llm;
 mod logging;
+mod outbox;
+mod pipeline;
+mod prompt;
+mod reorder;
+mod routing;
+mod storage;
+mod supervisor;
+mod transport;
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
 
 
-#[derive(Debug, Deserialize)]
-struct AproveData {
-    status: String,
-    text: String,
-}
-
-#[derive(Serialize, Deserialize, Debug, Default, Clone)]
-struct History {
-    messages: HashMap<i64, Vec<i32>>, // chat_id -> Vec<message_id>
-}
-
-const HISTORY_FILE: &str = "history.json";
-
-async fn load_history() -> Result<History> {
-    if tokio::fs::try_exists(HISTORY_FILE).await? {
-        let data = tokio::fs::read_to_string(HISTORY_FILE).await?;
-        Ok(serde_json::from_str(&data)?)
-    } else {
-        Ok(History {
-            messages: HashMap::new(),
-        })
-    }
-}
-
-async fn save_history(history: &History) -> Result<()> {
-    let data = serde_json::to_string_pretty(history)?;
-    tokio::fs::write(HISTORY_FILE, data).await?;
-    Ok(())
-}
-
 #[tokio::main]
 async fn main() -> Result<()> {
     dotenv().ok();
+    logging::logger::setup_logger()?;
 
     let config = crate::config::Config::load_config().await.unwrap();
 
     let api_id = config.main_config.app_id;
     let api_hash = config.main_config.api_hash.clone();
-    let target_username = config.bot_settings.target_channel.clone();
     let session_file = format!("{}.session", config.main_config.session_file_name);
-    let channels = config.bot_settings.source_channels;
-    let mistral_token = config.main_config.mistral_token;
+    let route_configs = config.bot_settings.routes()?;
+    // Источники маршрутов слушаем, даже если их нет в source_channels
+    let mut channels = config.bot_settings.source_channels.clone();
+    for source in route_configs.iter().flat_map(|route| &route.sources) {
+        if !channels.contains(source) {
+            channels.push(source.clone());
+        }
+    }
+    let providers = llm::build_providers(&config.llm, &config.main_config.mistral_token);
+    log_info!(
+        "LLM models: {}",
+        providers.iter().map(|provider| provider.name()).collect::<Vec<_>>().join(" -> ")
+    );
+
+    // Шаблоны проверяем до входа в Telegram, чтобы ошибка в конфиге не ждала авторизации
+    let default_prompt = PromptProfile::load_default(&config.prompt.base).await?;
+    let mut source_prompts = HashMap::new();
+    for (username, settings) in &config.prompt.sources {
+        let prompt = default_prompt.with_override(&format!("source:{}", username), settings).await?;
+        source_prompts.insert(username.clone(), prompt);
+    }
+    let mut route_prompts = Vec::new();
+    for route in &route_configs {
+        let prompt = match &route.prompt {
+            Some(settings) => Some(default_prompt.with_override(&format!("route:{}", route.name), settings).await?),
+            None => None,
+        };
+        route_prompts.push(prompt);
+    }
 
-    let client = Arc::new(login::login(api_id, api_hash, &session_file).await);
+    let client = login::login(api_id, api_hash, &session_file).await;
     let me = client.get_me().await?;
     log_info!("Username: {}", me.username().unwrap_or("No username"));
 
-    let target = client.resolve_username(&target_username).await?.unwrap();
-    log_info!("Target channel resolved: {:?}", target.name());
-
-    // let source_chats = resolve_channels(&mut client, channels).await?;
-    // println!("Resolved {} source channels", source_chats.len());
-
-    let mut input_chats: Vec<Chat> = Vec::new();
+    let transport = Arc::new(TelegramTransport::new(
+        client,
+        config.bot_settings.text_format,
+        config.bot_settings.reupload.clone(),
+    ));
+
+    let mut retention = Retention {
+        default: config.storage.retention.default,
+        ..Default::default()
+    };
+    let mut input_chats = Vec::new();
+    let mut source_ids: HashMap<String, i64> = HashMap::new();
+    let mut prompts = Prompts {
+        default: default_prompt,
+        sources: HashMap::new(),
+    };
     for chat in channels {
-        if let Some(ch) = client.resolve_username(&chat).await? {
-            input_chats.push(ch.clone());
-            log_info!("Not founded: {}", ch.name());
+        if let Some(ch) = transport.resolve_username(&chat).await? {
+            source_ids.insert(chat.clone(), ch.id);
+            if let Some(&limit) = config.storage.retention.per_chat.get(&chat) {
+                retention.per_chat.insert(ch.id, limit);
+            }
+            if let Some(prompt) = source_prompts.get(&chat) {
+                prompts.sources.insert(ch.id, Arc::clone(prompt));
+            }
+            log_info!("Source channel resolved: {}", ch.name);
+            input_chats.push(ch);
         } else {
-            log_info!("Not founded")
+            log_info!("Not founded: {}", chat)
         }
         sleep(Duration::from_secs(1)).await;
     }
 
-    process_chat(client, input_chats, target, mistral_token).await?;
-    
-
-    loop {
-        sleep(Duration::from_secs(3600)).await;
+    let mut routes = Vec::new();
+    for (route, prompt) in route_configs.iter().zip(route_prompts) {
+        let sources: HashSet<i64> = route.sources.iter().filter_map(|source| source_ids.get(source).copied()).collect();
+        if !route.sources.is_empty() && sources.is_empty() {
+            log_warn!("Route {} skipped: none of its sources resolved", route.name);
+            continue;
+        }
+        let target = transport
+            .resolve_username(&route.target)
+            .await?
+            .ok_or_else(|| format!("Target channel of route {} not found: {}", route.name, route.target))?;
+        log_info!("Route {} -> {}", route.name, target.name);
+        routes.push(Route::new(route, target.id, sources, prompt));
+        sleep(Duration::from_secs(1)).await;
+    }
+    for chat in &input_chats {
+        if !routes.iter().any(|route| route.accepts_source(chat.id)) {
+            log_warn!("Source {} is not used by any route", chat.name);
+        }
     }
-}
-
-async fn process_chat(
-    client: Arc<Client>,
-    input_chats: Vec<Chat>,
-    target_chat: Chat,
-    mistral_token: String,
-) -> Result<()> {
-    let history = Arc::new(Mutex::new(load_history().await?));
-
-    for chat in input_chats {
-        let chat_id = chat.id();
-        let history_ref = Arc::clone(&history);
-        let client = Arc::clone(&client);
-        let mistral = mistral_token.clone();
-        let target = target_chat.clone();
-
-        tokio::spawn(async move {
-            loop {
-                // Блокировка только на время работы с историей
-                let mut history = history_ref.lock().await;
-
-                let mut messages = client.iter_messages(chat.clone()).limit(10);
-                let mut groups: HashMap<i64, Vec<Message>> = HashMap::new();
-
-                // Получаем список сообщений для этого чата
-                let chat_messages = history.messages.entry(chat_id).or_insert_with(Vec::new);
-
-                while let Some(message) = messages.next().await.unwrap() {
-                    let msg_id = message.id();
-
-                    if chat_messages.contains(&msg_id) {
-                        continue;
-                    }
-
-                    if let Some(group_id) = message.grouped_id() {
-                        chat_messages.push(msg_id);
-                        groups.entry(group_id).or_default().push(message);
-                    } else {
-                        chat_messages.push(msg_id);
-                        if let Some(media) = message.media() {
-                            let mut has_relevant = false;
-                            let mut ai_text = String::new();
-                            let gend = generate(message.text(), &mistral).await.unwrap();
-                            if let Some(choice) = gend.choices.first() {
-                                match serde_json::from_str::<AproveData>(&choice.message.content) {
-                                    Ok(data) => {
-                                        if data.status == "релевантный" {
-                                            has_relevant = true;
-                                            ai_text = data.text;
-                                        }
-                                    }
-                                    Err(e) => log_error!("JSON parsing error: {:?}", e),
-                                }
-                            }
-                            if has_relevant {
-                                if !message.text().is_empty() {
-                                    match client.send_album(target.clone(), vec![InputMedia::caption(ai_text).copy_media(&media)]).await {
-                                        Ok(_) => {},
-                                        Err(e) => {
-                                            match e {                                
-                                                InvocationError::Rpc(rpc) => {
-                                                    if rpc.name == "FLOOD_WAIT" {
-                                                        log_info!("Wait... {:?}", rpc.value);
-                                                        sleep(Duration::from_secs(rpc.value.unwrap_or(10).into())).await;
-                                                    }
-                                                }
-                                                _ => {}
-                                            }
-                                        }
-                                    }
-                                }
-                            } else {
-                                continue;
-                            }
-                        } else {
-                            let mut has_relevant = false;
-                            let mut ai_text = String::new();
-                            let gend = generate(message.text(), &mistral).await.unwrap();
-                            if let Some(choice) = gend.choices.first() {
-                                match serde_json::from_str::<AproveData>(&choice.message.content) {
-                                    Ok(data) => {
-                                        if data.status == "релевантный" {
-                                            has_relevant = true;
-                                            ai_text = data.text;
-                                        }
-                                    }
-                                    Err(e) => log_error!("JSON parsing error: {:?}", e),
-                                }
-                            }
-                            if has_relevant {
-                                if !message.text().is_empty() {
-                                    match client.send_message(target.clone(), InputMessage::text(ai_text)).await {
-                                        Ok(_) => {},
-                                        Err(e) => {
-                                            match e {                                        
-                                                InvocationError::Rpc(rpc) => {
-                                                    if rpc.name == "FLOOD_WAIT" {
-                                                        log_info!("Wait... {:?}", rpc.value);
-                                                        sleep(Duration::from_secs(rpc.value.unwrap_or(10).into())).await;
-                                                    }
-                                                }
-                                                _ => {}
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    }
-                    sleep(Duration::from_secs(1)).await;
-                }
-
-                drop(history); // освобождаем мьютекс до обработки медиа
-
-                // --- Анализируем и отправляем медиа ---
-                for (_group_id, messages_in_group) in groups {
-                    let mut media_group: Vec<InputMedia> = Vec::new();
-                    let mut has_relevant = false;
-                    let mut _ai_text = String::new();
-
-                    for msg in &messages_in_group {
-                        if msg.text().is_empty() {
-                            continue;
-                        }
 
-                        let gend = generate(msg.text(), &mistral).await.unwrap();
-                        if let Some(choice) = gend.choices.first() {
-                            match serde_json::from_str::<AproveData>(&choice.message.content) {
-                                Ok(data) => {
-                                    if data.status == "релевантный" {
-                                        has_relevant = true;
-                                        _ai_text = data.text;
-                                    }
-                                }
-                                Err(e) => log_error!("JSON error parsing: {:?}", e),
-                            }
-                        }
-                    }
+    let storage = open_storage(&config.storage, retention.clone()).await?;
+    log_info!("Storage: {}", storage.name());
 
-                    if has_relevant {
-                        for msg in &messages_in_group {
-                            if let Some(media) = msg.media() {
-                                let caption = msg.text();
-                                media_group.push(InputMedia::caption(caption).copy_media(&media));
-                            }
-                        }
-                    }
+    let mut outbox = storage.load_outbox().await?;
+    if std::env::args().any(|arg| arg == "--redrive-dead-letters") {
+        let redriven = outbox.redrive();
+        for (chat_id, id, entry) in &redriven {
+            storage.save_outbox(*chat_id, &[(*id, Some(entry.clone()))]).await?;
+        }
+        log_info!("Re-driving {} dead letters", redriven.len());
+    } else if outbox.dead_letters() > 0 {
+        log_warn!("{} posts in dead letters, restart with --redrive-dead-letters to retry them", outbox.dead_letters());
+    }
 
-                    if !media_group.is_empty() {
-                        match client.send_album(target.clone(), media_group).await {
-                            Ok(_) => log_info!("Success send album"),
-                            Err(e) => {
-                                if let InvocationError::Rpc(rpc) = &e {
-                                    log_info!("Wait... {:?}", rpc.value);
-                                    if rpc.name == "FLOOD_WAIT" {
-                                        let delay = rpc.value.unwrap_or(10) as u64;
-                                        sleep(Duration::from_secs(delay)).await;
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
+    let pipeline = Pipeline::new(
+        Arc::clone(&transport),
+        Classifier::new(providers, config.bot_settings.classify_concurrency, config.llm.max_reasks)
+            .with_categories(route_configs.iter().flat_map(|route| route.categories.iter().cloned())),
+        Arc::clone(&storage),
+        retention,
+        input_chats,
+        routes,
+        prompts,
+        &config.bot_settings,
+    )
+    .await?;
+
+    let pipeline = Arc::new(pipeline);
+    let supervisor = Supervisor::new(config.bot_settings.supervisor.clone());
+    let result = tokio::select! {
+        result = Arc::clone(&pipeline).run(Arc::clone(&supervisor)) => result,
+        _ = shutdown_signal() => {
+            log_info!("Shutting down");
+            Ok(())
+        }
+        _ = supervisor.all_down() => {
+            for (name, health) in supervisor.health() {
+                log_error!(
+                    "{}: {} restarts, last error: {}",
+                    name,
+                    health.restarts,
+                    health.last_error.as_deref().unwrap_or("none")
+                );
+            }
+            Err("all source workers are down".into())
+        }
+    };
 
-                // Сохраняем историю раз в цикл
-                let history_to_save = Arc::clone(&history_ref);
-                let locked = history_to_save.lock().await;
-                if let Err(e) = save_history(&locked).await {
-                    log_error!("Error while saving messages history: {}", e);
-                }
+    // Фоновые задачи конвейера пишут в хранилище, закрываем его только после них
+    pipeline.shutdown(Duration::from_secs(config.bot_settings.shutdown_timeout_secs)).await;
+    if let Err(e) = storage.flush().await {
+        log_error!("Error while flushing storage: {}", e);
+    }
+    if let Err(e) = transport.client().session().save_to_file(&session_file) {
+        log_error!("Failed to save the session: {}", e);
+    }
+    result
+}
 
-                sleep(Duration::from_secs(1)).await;
+/// Ждём SIGINT (Ctrl+C) или SIGTERM (`docker stop`)
+async fn shutdown_signal() {
+    let terminate = async {
+        #[cfg(unix)]
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
             }
-        });
-    }
+            Err(e) => {
+                log_error!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+        #[cfg(not(unix))]
+        std::future::pending::<()>().await;
+    };
 
-    Ok(())
-}
\ No newline at end of file
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate => {}
+    }
+}
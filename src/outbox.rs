@@ -0,0 +1,278 @@
+//! Outbox: посты, которые приняты в обработку, но ещё не дошли до финала.
+//!
+//! Запись живёт здесь, пока пост не отправлен или не признан нерелевантным,
+//! после этого id переносится в историю. Посты, исчерпавшие попытки, остаются
+//! в состоянии `failed` (dead letters) и могут быть отправлены повторно
+//! через `--redrive-dead-letters`. Сохраняется через [`crate::storage::Storage`].
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{classifier::Verdict, config::OutboxConfig};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum OutboxState {
+    /// Принят, ждёт классификации
+    Pending,
+    /// Модель ответила. Ответы по имени профиля промпта. `None` (ответ не разобран)
+    /// встречается только в записях старых версий, как и отсутствие ответов:
+    /// такие посты классифицируются заново
+    Classified {
+        #[serde(default)]
+        verdicts: BTreeMap<String, Option<Verdict>>,
+    },
+    /// Отправлен, осталось перенести в историю
+    Sent,
+    /// Попытки исчерпаны, dead letter
+    Failed,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OutboxEntry {
+    #[serde(flatten)]
+    pub state: OutboxState,
+    pub grouped_id: Option<i64>,
+    pub attempts: u32,
+    pub last_error: Option<String>,
+    /// В какие target пост уже отправлен, при повторе их пропускаем
+    #[serde(default)]
+    pub delivered: Vec<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct Outbox {
+    pub entries: HashMap<i64, BTreeMap<i32, OutboxEntry>>, // chat_id -> message_id -> запись
+}
+
+impl Outbox {
+    pub fn get(&self, chat_id: i64, id: i32) -> Option<&OutboxEntry> {
+        self.entries.get(&chat_id)?.get(&id)
+    }
+
+    /// Берём пост в работу. `false`, если он уже есть в outbox (в любом состоянии)
+    pub fn track(&mut self, chat_id: i64, id: i32, grouped_id: Option<i64>) -> bool {
+        let chat = self.entries.entry(chat_id).or_default();
+        if chat.contains_key(&id) {
+            return false;
+        }
+        chat.insert(
+            id,
+            OutboxEntry {
+                state: OutboxState::Pending,
+                grouped_id,
+                attempts: 0,
+                last_error: None,
+                delivered: Vec::new(),
+            },
+        );
+        true
+    }
+
+    /// Записываем запись как есть (загрузка из хранилища)
+    pub fn put(&mut self, chat_id: i64, id: i32, entry: OutboxEntry) {
+        self.entries.entry(chat_id).or_default().insert(id, entry);
+    }
+
+    /// Сохранённое решение классификатора по профилю `prompt`, чтобы не спрашивать модель повторно
+    pub fn decision(&self, chat_id: i64, id: i32, prompt: &str) -> Option<Verdict> {
+        match &self.get(chat_id, id)?.state {
+            OutboxState::Classified { verdicts } => verdicts.get(prompt).cloned().flatten(),
+            _ => None,
+        }
+    }
+
+    /// Запоминаем решение модели по профилю `prompt`
+    pub fn set_decision(&mut self, chat_id: i64, ids: &[i32], prompt: &str, verdict: &Verdict) {
+        for entry in self.entries_mut(chat_id, ids) {
+            if !matches!(entry.state, OutboxState::Classified { .. }) {
+                entry.state = OutboxState::Classified {
+                    verdicts: BTreeMap::new(),
+                };
+            }
+            if let OutboxState::Classified { verdicts } = &mut entry.state {
+                verdicts.insert(prompt.to_string(), Some(verdict.clone()));
+            }
+        }
+    }
+
+    /// Пост отправлен в `target`
+    pub fn mark_delivered(&mut self, chat_id: i64, ids: &[i32], target: i64) {
+        for entry in self.entries_mut(chat_id, ids) {
+            if !entry.delivered.contains(&target) {
+                entry.delivered.push(target);
+            }
+        }
+    }
+
+    pub fn delivered(&self, chat_id: i64, id: i32) -> &[i64] {
+        self.get(chat_id, id).map_or(&[], |entry| &entry.delivered)
+    }
+
+    pub fn set_state(&mut self, chat_id: i64, ids: &[i32], state: OutboxState) {
+        for entry in self.entries_mut(chat_id, ids) {
+            entry.state = state.clone();
+        }
+    }
+
+    /// Фиксируем неудачную попытку и возвращаем задержку перед следующей.
+    /// `None`, если попытки по `retry.max_attempts` исчерпаны: записи уже в dead letters
+    pub fn record_failure(&mut self, chat_id: i64, ids: &[i32], error: &str, retry: &OutboxConfig) -> Option<Duration> {
+        let mut attempts = 0;
+        for entry in self.entries_mut(chat_id, ids) {
+            entry.attempts += 1;
+            entry.last_error = Some(error.to_string());
+            attempts = attempts.max(entry.attempts);
+        }
+        if attempts >= retry.max_attempts {
+            self.set_state(chat_id, ids, OutboxState::Failed);
+            return None;
+        }
+        Some(retry.backoff(attempts))
+    }
+
+    /// Убираем записи, дошедшие до финала
+    pub fn remove(&mut self, chat_id: i64, ids: &[i32]) {
+        if let Some(chat) = self.entries.get_mut(&chat_id) {
+            for id in ids {
+                chat.remove(id);
+            }
+            if chat.is_empty() {
+                self.entries.remove(&chat_id);
+            }
+        }
+    }
+
+    /// Незавершённые записи (всё, кроме dead letters): chat_id -> id
+    pub fn unfinished(&self) -> Vec<(i64, Vec<i32>)> {
+        self.entries
+            .iter()
+            .map(|(&chat_id, chat)| {
+                let ids = chat
+                    .iter()
+                    .filter(|(_, entry)| entry.state != OutboxState::Failed)
+                    .map(|(&id, _)| id)
+                    .collect::<Vec<_>>();
+                (chat_id, ids)
+            })
+            .filter(|(_, ids)| !ids.is_empty())
+            .collect()
+    }
+
+    /// Разбиваем outbox по источникам, чтобы у каждого было своё состояние
+    pub fn split(self) -> HashMap<i64, Outbox> {
+        self.entries
+            .into_iter()
+            .map(|(chat_id, chat)| {
+                let entries = HashMap::from([(chat_id, chat)]);
+                (chat_id, Outbox { entries })
+            })
+            .collect()
+    }
+
+    pub fn dead_letters(&self) -> usize {
+        self.entries
+            .values()
+            .flat_map(|chat| chat.values())
+            .filter(|entry| entry.state == OutboxState::Failed)
+            .count()
+    }
+
+    /// Возвращаем dead letters в очередь с нуля попыток.
+    /// Отдаём изменённые записи, чтобы сохранить их в хранилище
+    pub fn redrive(&mut self) -> Vec<(i64, i32, OutboxEntry)> {
+        let mut redriven = Vec::new();
+        for (&chat_id, chat) in self.entries.iter_mut() {
+            for (&id, entry) in chat.iter_mut() {
+                if entry.state == OutboxState::Failed {
+                    entry.state = OutboxState::Pending;
+                    entry.attempts = 0;
+                    redriven.push((chat_id, id, entry.clone()));
+                }
+            }
+        }
+        redriven
+    }
+
+    fn entries_mut<'a>(&'a mut self, chat_id: i64, ids: &'a [i32]) -> impl Iterator<Item = &'a mut OutboxEntry> {
+        self.entries
+            .get_mut(&chat_id)
+            .into_iter()
+            .flat_map(move |chat| chat.iter_mut().filter(move |(id, _)| ids.contains(id)).map(|(_, entry)| entry))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHAT: i64 = 1;
+
+    fn retry() -> OutboxConfig {
+        OutboxConfig {
+            max_attempts: 5,
+            backoff_base_secs: 5,
+            backoff_max_secs: 30,
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let mut outbox = Outbox::default();
+        outbox.track(CHAT, 1, None);
+
+        let delays: Vec<_> = (0..4).map(|_| outbox.record_failure(CHAT, &[1], "timeout", &retry())).collect();
+        let expected = [5, 10, 20, 30].map(|secs| Some(Duration::from_secs(secs)));
+        assert_eq!(delays, expected);
+        assert_eq!(outbox.get(CHAT, 1).unwrap().attempts, 4);
+    }
+
+    #[test]
+    fn exhausted_attempts_move_to_dead_letters() {
+        let mut outbox = Outbox::default();
+        outbox.track(CHAT, 1, Some(7));
+        outbox.track(CHAT, 2, Some(7));
+        outbox.track(CHAT, 3, None);
+        let retry = OutboxConfig {
+            max_attempts: 2,
+            ..retry()
+        };
+
+        assert!(outbox.record_failure(CHAT, &[1, 2], "timeout", &retry).is_some());
+        assert_eq!(outbox.record_failure(CHAT, &[1, 2], "FLOOD", &retry), None);
+
+        for id in [1, 2] {
+            let entry = outbox.get(CHAT, id).unwrap();
+            assert_eq!(entry.state, OutboxState::Failed);
+            assert_eq!((entry.attempts, entry.last_error.as_deref()), (2, Some("FLOOD")));
+        }
+        assert_eq!(outbox.dead_letters(), 2);
+        assert_eq!(outbox.unfinished(), [(CHAT, vec![3])]);
+    }
+
+    #[test]
+    fn redrive_resets_dead_letters() {
+        let mut outbox = Outbox::default();
+        outbox.track(CHAT, 1, None);
+        outbox.track(CHAT, 2, None);
+        let retry = OutboxConfig {
+            max_attempts: 1,
+            ..retry()
+        };
+        outbox.record_failure(CHAT, &[1], "timeout", &retry);
+
+        let redriven = outbox.redrive();
+        assert_eq!(redriven.len(), 1);
+        let (chat_id, id, entry) = &redriven[0];
+        assert_eq!((*chat_id, *id), (CHAT, 1));
+        assert_eq!((&entry.state, entry.attempts), (&OutboxState::Pending, 0));
+        assert_eq!(outbox.dead_letters(), 0);
+        assert_eq!(outbox.unfinished(), [(CHAT, vec![1, 2])]);
+        // Повторный redrive ничего не находит
+        assert!(outbox.redrive().is_empty());
+    }
+}
@@ -0,0 +1,1812 @@
+//! Обработка постов из каналов-источников: приём обновлений, сборка альбомов,
+//! классификация и отправка в target канал.
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use tokio::{
+    sync::{mpsc, watch, Mutex, Notify, OnceCell},
+    task::JoinHandle,
+    time::{sleep, timeout},
+};
+
+use crate::{
+    bot::types::{OutgoingPost, PostKind},
+    classifier::{Classifier, Verdict},
+    config::{AlbumCaptions, BotSettings, ImageConfig, OnDelete, OutboxConfig, Overflow, RepostMode, SyncConfig},
+    handlers::MediaGroupHandler,
+    history::{Decision, History, Retention, TargetRef},
+    llm::Image,
+    log_debug, log_error, log_info, log_warn,
+    outbox::{Outbox, OutboxEntry, OutboxState},
+    prompt::{PromptProfile, Prompts},
+    reorder::{ReorderBuffer, TimestampWindow},
+    routing::{self, Route},
+    storage::Storage,
+    supervisor::Supervisor,
+    transport::{
+        split::{CAPTION_LIMIT, TEXT_LIMIT},
+        AlbumItem, ChatTransport, ChatUpdate, ResolvedChat, SourcePost, TransportError,
+    },
+};
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Сколько последних постов проверяем для источника, по которому ещё нет отметки
+const STARTUP_BACKFILL: usize = 10;
+
+/// Размер страницы истории при догонке, максимум Telegram — 100
+const CATCH_UP_PAGE: usize = 100;
+
+/// Больше частей в альбоме Telegram не бывает
+const ALBUM_SIZE: i32 = 10;
+
+/// Как часто проверяем общее окно режима cross_source
+const WINDOW_TICK: Duration = Duration::from_millis(250);
+
+/// Что публикуем в один target
+struct Delivery<M> {
+    target: i64,
+    outgoing: OutgoingPost<M>,
+}
+
+/// Итог обработки поста
+enum Outcome<M> {
+    /// По одной доставке на target подходящих маршрутов
+    Publish(Vec<Delivery<M>>),
+    /// Ни один маршрут не подошёл или пост пустой, публиковать нечего
+    Skip,
+    /// Попытки исчерпаны
+    Failed(String),
+}
+
+/// Готовый к публикации пост: id и дата оригинала нужны для упорядочивания
+struct Prepared<M> {
+    chat_id: i64,
+    /// Все id поста в источнике, у альбома их несколько
+    ids: Vec<i32>,
+    date: i64,
+    /// Правка оригинала, с которой собраны доставки
+    edit_date: Option<i64>,
+    deliveries: Vec<Delivery<M>>,
+}
+
+/// Задание обработчику источника
+enum Intake<M> {
+    Post(SourcePost<M>),
+    /// Проверить историю источника на пропущенные посты
+    CatchUp,
+    /// Пост источника отредактирован
+    Edited(SourcePost<M>),
+    /// Посты источника удалены
+    Deleted(Vec<i32>),
+    /// Сверить последние опубликованные посты с источником
+    Recheck,
+}
+
+/// Состояние одного источника. Блокировки у каждого источника свои,
+/// так что медленная модель или отправка в одном не задерживает остальные
+struct ChatState<M> {
+    /// Название канала для промпта
+    title: String,
+    history: Mutex<History>,
+    outbox: Mutex<Outbox>,
+    /// Очередь публикации источника
+    ordering: Mutex<ReorderBuffer<Prepared<M>>>,
+    /// Входящие для обработчика источника. Очередь переживает его перезапуски
+    intake: mpsc::UnboundedSender<Intake<M>>,
+    queue: Mutex<mpsc::UnboundedReceiver<Intake<M>>>,
+}
+
+/// Картинки поста для модели. Скачиваются при первом обращении: решение
+/// из outbox их не требует
+struct PostImages<'a, M> {
+    media: Vec<&'a M>,
+    loaded: OnceCell<Vec<Image>>,
+}
+
+impl<'a, M> PostImages<'a, M> {
+    fn new(media: impl IntoIterator<Item = &'a M>) -> Self {
+        Self {
+            media: media.into_iter().collect(),
+            loaded: OnceCell::new(),
+        }
+    }
+}
+
+/// Счётчик постов, правок и удалений в обработке, их дожидаемся при остановке
+#[derive(Default)]
+struct InFlight {
+    count: AtomicUsize,
+    idle: Notify,
+}
+
+struct InFlightGuard(Arc<InFlight>);
+
+impl InFlight {
+    fn enter(self: &Arc<Self>) -> InFlightGuard {
+        self.count.fetch_add(1, Ordering::SeqCst);
+        InFlightGuard(Arc::clone(self))
+    }
+
+    async fn wait_idle(&self) {
+        loop {
+            let notified = self.idle.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+            if self.count.load(Ordering::SeqCst) == 0 {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        if self.0.count.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.0.idle.notify_waiters();
+        }
+    }
+}
+
+pub struct Pipeline<T: ChatTransport> {
+    transport: Arc<T>,
+    classifier: Classifier,
+    /// Каналы-источники, обновления из остальных чатов игнорируются
+    chats: HashMap<i64, ChatState<T::Media>>,
+    routes: Vec<Route>,
+    prompts: Prompts,
+    media_groups: MediaGroupHandler<SourcePost<T::Media>>,
+    album_captions: AlbumCaptions,
+    /// Посты старше этого при догонке пропускаем
+    catch_up_max_age: Duration,
+    /// Общее окно упорядочивания по времени, если включён `ordering.cross_source`
+    cross_source: Option<Mutex<TimestampWindow<Prepared<T::Media>>>>,
+    retry: OutboxConfig,
+    images: ImageConfig,
+    sync: SyncConfig,
+    storage: Arc<dyn Storage>,
+    /// Сколько обработанных id держим в памяти на источник
+    retention: Retention,
+    /// `true` после начала остановки: обработчики источников больше не берут посты
+    stopping: watch::Sender<bool>,
+    in_flight: Arc<InFlight>,
+    /// Фоновые задачи сборки альбомов и общего окна, их дожидаемся при остановке
+    timers: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl<T: ChatTransport> Pipeline<T> {
+    #[allow(clippy::too_many_arguments)]
+    pub async fn new(
+        transport: Arc<T>,
+        classifier: Classifier,
+        storage: Arc<dyn Storage>,
+        retention: Retention,
+        sources: Vec<ResolvedChat>,
+        routes: Vec<Route>,
+        prompts: Prompts,
+        settings: &BotSettings,
+    ) -> Result<Self> {
+        let mut history = storage.load_history().await?.split();
+        let mut outbox = storage.load_outbox().await?.split();
+
+        let chats = sources
+            .into_iter()
+            .map(|source| {
+                let chat_id = source.id;
+                let (intake, queue) = mpsc::unbounded_channel();
+                let state = ChatState {
+                    title: source.name,
+                    history: Mutex::new(history.remove(&chat_id).unwrap_or_default()),
+                    outbox: Mutex::new(outbox.remove(&chat_id).unwrap_or_default()),
+                    ordering: Mutex::new(ReorderBuffer::default()),
+                    intake,
+                    queue: Mutex::new(queue),
+                };
+                (chat_id, state)
+            })
+            .collect();
+        let cross_source = settings
+            .ordering
+            .cross_source
+            .then(|| Mutex::new(TimestampWindow::new(Duration::from_millis(settings.ordering.window_ms))));
+
+        Ok(Self {
+            transport,
+            classifier,
+            chats,
+            routes,
+            prompts,
+            media_groups: MediaGroupHandler::new(Duration::from_millis(settings.album_timeout_ms)).await,
+            album_captions: settings.album_captions,
+            catch_up_max_age: Duration::from_secs(settings.catch_up_max_age_secs),
+            cross_source,
+            retry: settings.outbox.clone(),
+            images: settings.images.clone(),
+            sync: settings.sync.clone(),
+            storage,
+            retention,
+            stopping: watch::Sender::new(false),
+            in_flight: Arc::default(),
+            timers: Mutex::default(),
+        })
+    }
+
+    /// Основной цикл: запускаем обработчики источников под `supervisor`
+    /// и раздаём им обновления из `next_update`
+    pub async fn run(self: Arc<Self>, supervisor: Arc<Supervisor>) -> Result<()> {
+        {
+            let mut timers = self.timers.lock().await;
+            timers.push(tokio::spawn(Arc::clone(&self).flush_albums()));
+            timers.push(tokio::spawn(Arc::clone(&self).flush_window()));
+            timers.push(tokio::spawn(Arc::clone(&self).schedule_rechecks()));
+        }
+
+        self.resume_outbox().await;
+        for &chat_id in self.chats.keys() {
+            let this = Arc::clone(&self);
+            supervisor.spawn(format!("source {}", chat_id), move || {
+                Arc::clone(&this).source_worker(chat_id)
+            });
+        }
+
+        // После ошибки получения обновлений часть постов могла пройти мимо,
+        // поэтому на первом успешном обновлении снова догоняем
+        let mut reconnected = false;
+        loop {
+            let update = with_flood_wait(|| self.transport.next_update()).await;
+            if update.is_ok() && reconnected {
+                reconnected = false;
+                log_info!("Updates are back, catching up");
+                for chat in self.chats.values() {
+                    let _ = chat.intake.send(Intake::CatchUp);
+                }
+            }
+
+            match update {
+                Ok(ChatUpdate::NewPost(post)) => {
+                    if let Some(chat) = self.chats.get(&post.chat_id) {
+                        let _ = chat.intake.send(Intake::Post(post));
+                    }
+                }
+                Ok(ChatUpdate::EditedPost(post)) if self.sync.edits => {
+                    if let Some(chat) = self.chats.get(&post.chat_id) {
+                        let _ = chat.intake.send(Intake::Edited(post));
+                    }
+                }
+                Ok(ChatUpdate::DeletedPosts { chat_id, ids }) if self.sync.deletions => {
+                    if let Some(chat) = self.chats.get(&chat_id) {
+                        let _ = chat.intake.send(Intake::Deleted(ids));
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    log_error!("Error while receiving updates: {}", e);
+                    reconnected = true;
+                    sleep(Duration::from_secs(1)).await;
+                }
+            }
+        }
+    }
+
+    /// Обработчик источника: догоняет пропущенное и разбирает свою очередь.
+    /// Ошибка догонки завершает обработчик, супервизор перезапустит его,
+    /// и догонка начнётся заново
+    async fn source_worker(self: Arc<Self>, chat_id: i64) -> Result<()> {
+        let chat = self.chats.get(&chat_id).ok_or("unknown source chat")?;
+        let mut queue = chat.queue.lock().await;
+        let mut stopping = self.stopping.subscribe();
+
+        self.catch_up(chat_id).await?;
+        loop {
+            if *stopping.borrow() {
+                return Ok(());
+            }
+            let intake = tokio::select! {
+                intake = queue.recv() => intake,
+                _ = stopping.changed() => continue,
+            };
+            let Some(intake) = intake else {
+                return Ok(());
+            };
+            match intake {
+                Intake::Post(post) => self.dispatch(post).await,
+                Intake::CatchUp => self.catch_up(chat_id).await?,
+                Intake::Edited(post) => {
+                    let _guard = self.in_flight.enter();
+                    self.apply_edit(post).await;
+                }
+                Intake::Deleted(ids) => {
+                    let _guard = self.in_flight.enter();
+                    self.apply_deletion(chat_id, &ids).await;
+                }
+                Intake::Recheck => {
+                    let _guard = self.in_flight.enter();
+                    self.recheck(chat_id).await;
+                }
+            }
+        }
+    }
+
+    /// Останавливаемся: обработчики источников перестают брать посты, а начатые
+    /// дорабатывают не дольше `deadline`. Недособранные альбомы уходят в обработку сразу,
+    /// общее окно выпускается целиком. Недоделанное остаётся в outbox
+    /// и продолжится после перезапуска. Хранилище можно закрывать после возврата
+    pub async fn shutdown(&self, deadline: Duration) {
+        self.stopping.send_replace(true);
+
+        let finished = async {
+            let timers = std::mem::take(&mut *self.timers.lock().await);
+            for timer in timers {
+                let _ = timer.await;
+            }
+            self.in_flight.wait_idle().await;
+            // Обработанные посты дошли до окна, новых уже не будет
+            if let Some(window) = &self.cross_source {
+                let released = window.lock().await.drain_all();
+                for prepared in released {
+                    self.deliver(prepared).await;
+                }
+            }
+        };
+        if timeout(deadline, finished).await.is_err() {
+            log_warn!(
+                "{} posts still in progress after {:?}, they will resume from outbox",
+                self.in_flight.count.load(Ordering::SeqCst),
+                deadline
+            );
+        }
+    }
+
+    async fn catch_up(self: &Arc<Self>, chat_id: i64) -> Result<()> {
+        let missed = self.collect_missed(chat_id).await?;
+        if !missed.is_empty() {
+            log_info!("Catching up {} posts from {}", missed.len(), chat_id);
+        }
+        for post in missed {
+            self.dispatch(post).await;
+        }
+        Ok(())
+    }
+
+    /// Листаем историю источника назад до последнего обработанного id
+    /// (или до `catch_up_max_age`). Возвращаем пропущенные посты от старых к новым
+    async fn collect_missed(&self, chat_id: i64) -> Result<Vec<SourcePost<T::Media>>> {
+        let Some(chat) = self.chats.get(&chat_id) else {
+            return Ok(Vec::new());
+        };
+        let mark = chat.history.lock().await.high_water.get(&chat_id).copied();
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+        let cutoff = now - self.catch_up_max_age.as_secs() as i64;
+
+        let mut missed = Vec::new();
+        let mut offset_id = None;
+        'paging: loop {
+            let page = with_flood_wait(|| self.transport.history_page(chat_id, offset_id, CATCH_UP_PAGE)).await?;
+            if page.is_empty() {
+                break;
+            }
+
+            for post in page {
+                let reached_mark = mark.is_some_and(|mark| post.id <= mark);
+                if reached_mark || post.date < cutoff {
+                    break 'paging;
+                }
+                offset_id = Some(post.id);
+                missed.push(post);
+
+                // Первый запуск для источника: как раньше, только последние посты
+                if mark.is_none() && missed.len() >= STARTUP_BACKFILL {
+                    break 'paging;
+                }
+            }
+        }
+
+        missed.reverse();
+        Ok(missed)
+    }
+
+    /// Раз в `sync.check_interval_secs` просим обработчики сверить опубликованное, до остановки.
+    /// Первая сверка сразу при запуске: ловит правки и удаления, сделанные во время простоя
+    async fn schedule_rechecks(self: Arc<Self>) {
+        if !(self.sync.edits || self.sync.deletions) || self.sync.check_interval_secs == 0 {
+            return;
+        }
+        let interval = Duration::from_secs(self.sync.check_interval_secs);
+        loop {
+            for chat in self.chats.values() {
+                let _ = chat.intake.send(Intake::Recheck);
+            }
+            if !self.tick(interval).await {
+                return;
+            }
+        }
+    }
+
+    /// Перечитываем последние опубликованные посты источника и переносим правки
+    /// и удаления, обновления о которых не пришли
+    async fn recheck(&self, chat_id: i64) {
+        let Some(chat) = self.chats.get(&chat_id) else {
+            return;
+        };
+        let ids = chat.history.lock().await.recent_published(chat_id, self.sync.check_depth);
+        if ids.is_empty() {
+            return;
+        }
+        let posts = match self.transport.posts_by_id(chat_id, &ids).await {
+            Ok(posts) => posts,
+            Err(e) => {
+                log_warn!("Failed to recheck posts of {}: {}", chat_id, e);
+                return;
+            }
+        };
+
+        if self.sync.deletions {
+            let gone: Vec<i32> = ids.iter().copied().filter(|id| !posts.iter().any(|post| post.id == *id)).collect();
+            if !gone.is_empty() {
+                self.apply_deletion(chat_id, &gone).await;
+            }
+        }
+        if !self.sync.edits {
+            return;
+        }
+
+        let mut albums = Vec::new();
+        for post in posts {
+            if !self.is_new_edit(chat, &post).await {
+                continue;
+            }
+            // Альбом правим один раз, сколько бы его частей ни изменилось
+            if let Some(group_id) = post.grouped_id {
+                if albums.contains(&group_id) {
+                    continue;
+                }
+                albums.push(group_id);
+            }
+            self.apply_edit(post).await;
+        }
+    }
+
+    /// Правка новее той, с которой опубликованы копии поста. Пост, ещё не дошедший
+    /// до финала, не трогаем: его копии догонит следующая сверка
+    async fn is_new_edit(&self, chat: &ChatState<T::Media>, post: &SourcePost<T::Media>) -> bool {
+        let Some(edit_date) = post.edit_date else {
+            return false;
+        };
+        if chat.outbox.lock().await.get(post.chat_id, post.id).is_some() {
+            return false;
+        }
+        let history = chat.history.lock().await;
+        let copies = history.copies(post.chat_id, post.id);
+        !copies.is_empty() && copies.iter().all(|copy| copy.edit_date.is_none_or(|date| date < edit_date))
+    }
+
+    /// Правка опубликованного поста: заново классифицируем его и приводим копии
+    /// к новому ответу модели. Копия в target, куда пост больше не подходит, удаляется
+    async fn apply_edit(&self, post: SourcePost<T::Media>) {
+        let Some(chat) = self.chats.get(&post.chat_id) else {
+            return;
+        };
+        if !self.is_new_edit(chat, &post).await {
+            return;
+        }
+        let chat_id = post.chat_id;
+
+        // Альбом классифицируется целиком, подпись модели стоит на первом элементе
+        let (ids, edit_date, prepared) = match post.grouped_id {
+            Some(group_id) => {
+                let posts = match self.album_of(&post, group_id).await {
+                    Ok(posts) => posts,
+                    Err(e) => {
+                        log_warn!("Failed to load album {} of edited post {}: {}", group_id, post.id, e);
+                        return;
+                    }
+                };
+                let mut ids: Vec<i32> = posts.iter().map(|post| post.id).collect();
+                ids.sort_unstable();
+                let edit_date = posts.iter().filter_map(|post| post.edit_date).max();
+                (ids, edit_date, self.prepare_album(posts, true).await)
+            }
+            None => (vec![post.id], post.edit_date, self.prepare_single(post, true).await),
+        };
+        let deliveries = match prepared {
+            Ok(deliveries) => deliveries.unwrap_or_default(),
+            Err(e) => {
+                log_warn!("Edit of {:?} from {} is not applied: {}", ids, chat_id, e);
+                return;
+            }
+        };
+
+        let copies = {
+            let history = chat.history.lock().await;
+            copies_by_target(&history, chat_id, &ids)
+        };
+        for (target, message_ids) in copies {
+            let delivery = deliveries.iter().find(|delivery| delivery.target == target);
+            let message_ids = &message_ids;
+            let result = with_flood_wait(|| async move {
+                match delivery {
+                    Some(delivery) => self.update(target, message_ids, &delivery.outgoing).await,
+                    None if message_ids.is_empty() => Ok(Vec::new()),
+                    None => self.transport.delete(target, message_ids).await.map(|_| Vec::new()),
+                }
+            })
+            .await;
+
+            match result {
+                Ok(target_ids) => {
+                    match delivery {
+                        Some(_) => log_info!("Edit of {:?} from {} applied to {}", ids, chat_id, target),
+                        None => log_info!("Edited {:?} from {} no longer fits {}, copy removed", ids, chat_id, target),
+                    }
+                    self.record_mapping(chat, chat_id, &ids, target, target_ids, edit_date).await;
+                }
+                Err(e) => log_warn!("Failed to apply edit of {:?} from {} to {}: {}", ids, chat_id, target, e),
+            }
+        }
+    }
+
+    /// Посты удалены в источнике: с их копиями поступаем по `on_delete` маршрута
+    /// target и забываем их. Если с копией не вышло, её повторит следующая сверка
+    async fn apply_deletion(&self, chat_id: i64, ids: &[i32]) {
+        let Some(chat) = self.chats.get(&chat_id) else {
+            return;
+        };
+
+        // Части альбома помечаем одним ответом и удаляем одним запросом
+        let (deleted, copies) = {
+            let history = chat.history.lock().await;
+            let deleted: Vec<i32> = ids.iter().copied().filter(|&id| !history.copies(chat_id, id).is_empty()).collect();
+            (deleted, copies_by_target(&history, chat_id, ids))
+        };
+        if deleted.is_empty() {
+            return;
+        }
+
+        let (mut handled, mut failed) = (Vec::new(), Vec::new());
+        for (target, message_ids) in copies {
+            let Some(&first) = message_ids.first() else {
+                continue;
+            };
+            // Маршрута могло не остаться в конфиге, тогда копию просто удаляем
+            let route = self
+                .routes
+                .iter()
+                .find(|route| route.target == target && route.accepts_source(chat_id));
+            let policy = route.map_or(OnDelete::Delete, |route| route.on_delete);
+            let message_ids = &message_ids;
+            let result = with_flood_wait(|| async move {
+                match (policy, route) {
+                    (OnDelete::Mark, Some(route)) => self
+                        .transport
+                        .send_text(target, &route.deleted_notice, Some(first), false)
+                        .await
+                        .map(drop),
+                    (OnDelete::Keep, _) => Ok(()),
+                    _ => self.transport.delete(target, message_ids).await,
+                }
+            })
+            .await;
+
+            match result {
+                Ok(()) => {
+                    log_info!("Posts {:?} deleted in {}, copy in {}: {:?}", deleted, chat_id, target, policy);
+                    handled.push(target);
+                }
+                Err(e) => {
+                    log_warn!("Failed to handle deletion of {:?} from {} in {}: {}", deleted, chat_id, target, e);
+                    failed.push(target);
+                }
+            }
+        }
+
+        for &id in &deleted {
+            if failed.is_empty() {
+                chat.history.lock().await.remove_mapping(chat_id, id);
+                if let Err(e) = self.storage.remove_mapping(chat_id, id).await {
+                    log_error!("Error while saving message mapping: {}", e);
+                }
+                continue;
+            }
+            // Обработанные копии отмечаем пустыми, чтобы повтор их не трогал
+            for &target in &handled {
+                let copy = TargetRef {
+                    chat_id: target,
+                    message_ids: Vec::new(),
+                    edit_date: None,
+                };
+                chat.history.lock().await.add_mapping(chat_id, id, copy.clone());
+                if let Err(e) = self.storage.record_mapping(chat_id, id, &copy).await {
+                    log_error!("Error while saving message mapping: {}", e);
+                }
+            }
+        }
+    }
+
+    /// Все части альбома `group_id`, к которому относится `post`. В альбоме не больше
+    /// 10 частей, и id у них подряд, так что ищем среди соседних id
+    async fn album_of(
+        &self,
+        post: &SourcePost<T::Media>,
+        group_id: i64,
+    ) -> std::result::Result<Vec<SourcePost<T::Media>>, TransportError> {
+        let ids: Vec<i32> = (post.id.saturating_sub(ALBUM_SIZE - 1)..=post.id.saturating_add(ALBUM_SIZE - 1))
+            .filter(|&id| id > 0)
+            .collect();
+        let posts = self.transport.posts_by_id(post.chat_id, &ids).await?;
+        Ok(posts.into_iter().filter(|post| post.grouped_id == Some(group_id)).collect())
+    }
+
+    /// Поднимаем незавершённые записи outbox после перезапуска
+    async fn resume_outbox(self: &Arc<Self>) {
+        for (&chat_id, chat) in &self.chats {
+            let unfinished = chat.outbox.lock().await.unfinished();
+            for (_, ids) in unfinished {
+                // Отправленные, но не перенесённые в историю, просто завершаем
+                let (sent, ids): (Vec<i32>, Vec<i32>) = {
+                    let outbox = chat.outbox.lock().await;
+                    ids.into_iter().partition(|&id| {
+                        outbox.get(chat_id, id).is_some_and(|entry| entry.state == OutboxState::Sent)
+                    })
+                };
+                self.finish(chat, chat_id, &sent).await;
+                if ids.is_empty() {
+                    continue;
+                }
+
+                match self.transport.posts_by_id(chat_id, &ids).await {
+                    Ok(posts) => {
+                        // Пост удалён из источника, публиковать нечего
+                        let gone: Vec<i32> = ids.iter().copied().filter(|id| !posts.iter().any(|post| post.id == *id)).collect();
+                        self.finish(chat, chat_id, &gone).await;
+
+                        log_info!("Resuming {} posts from outbox of {}", posts.len(), chat_id);
+                        for post in posts {
+                            self.admit(post).await;
+                        }
+                    }
+                    Err(e) => log_error!("Failed to resume outbox of {}: {}", chat_id, e),
+                }
+            }
+        }
+    }
+
+    /// Берём новый пост в работу: фиксируем его в outbox и обновляем отметку источника
+    async fn dispatch(self: &Arc<Self>, post: SourcePost<T::Media>) {
+        let Some(chat) = self.chats.get(&post.chat_id) else {
+            return;
+        };
+        if chat.history.lock().await.is_processed(post.chat_id, post.id) {
+            return;
+        }
+
+        {
+            let mut outbox = chat.outbox.lock().await;
+            if !outbox.track(post.chat_id, post.id, post.grouped_id) {
+                return;
+            }
+            self.persist_outbox(&outbox, post.chat_id, &[post.id]).await;
+        }
+
+        let raised = chat.history.lock().await.raise_high_water(post.chat_id, post.id);
+        if raised && let Err(e) = self.storage.set_high_water(post.chat_id, post.id).await {
+            log_error!("Error while saving high-water mark: {}", e);
+        }
+
+        self.admit(post).await;
+    }
+
+    /// Резервируем посту место в очереди публикации и отправляем на обработку.
+    /// Части альбома копятся в `MediaGroupHandler` до истечения таймаута
+    async fn admit(self: &Arc<Self>, post: SourcePost<T::Media>) {
+        if let Some(chat) = self.chats.get(&post.chat_id) {
+            chat.ordering.lock().await.reserve(post.id);
+        }
+
+        if let Some(group_id) = post.grouped_id {
+            self.media_groups.add_media(group_id, post).await;
+            return;
+        }
+
+        let this = Arc::clone(self);
+        let guard = self.in_flight.enter();
+        tokio::spawn(async move {
+            let _guard = guard;
+            let (chat_id, id, date, edit_date) = (post.chat_id, post.id, post.date, post.edit_date);
+            let worker = Arc::clone(&this);
+            let outcome = settle(tokio::spawn(async move { worker.prepare_single(post, false).await })).await;
+            this.publish(chat_id, vec![id], date, edit_date, outcome).await;
+        });
+    }
+
+    /// Периодически забираем собранные альбомы. При остановке забираем
+    /// все оставшиеся, не дожидаясь таймаута
+    async fn flush_albums(self: Arc<Self>) {
+        let tick = self.media_groups.timeout / 2;
+        loop {
+            let running = self.tick(tick).await;
+
+            let groups = if running {
+                self.media_groups.get_expired_groups().await
+            } else {
+                self.media_groups.take_all_groups().await
+            };
+            for (group_id, posts) in groups {
+                self.process_album(group_id, posts);
+            }
+            if !running {
+                return;
+            }
+        }
+    }
+
+    fn process_album(self: &Arc<Self>, group_id: i64, posts: Vec<SourcePost<T::Media>>) {
+        let this = Arc::clone(self);
+        let guard = self.in_flight.enter();
+        tokio::spawn(async move {
+            let _guard = guard;
+            let Some(first) = posts.iter().min_by_key(|post| post.id) else {
+                return;
+            };
+            let (chat_id, date) = (first.chat_id, first.date);
+            let ids: Vec<i32> = posts.iter().map(|post| post.id).collect();
+            let edit_date = posts.iter().filter_map(|post| post.edit_date).max();
+
+            let worker = Arc::clone(&this);
+            let outcome = settle(tokio::spawn(async move { worker.prepare_album(posts, false).await })).await;
+            if let Outcome::Skip = outcome {
+                log_debug!("Nothing to publish for album {}", group_id);
+            }
+            this.publish(chat_id, ids, date, edit_date, outcome).await;
+        });
+    }
+
+    /// Ждём `period`. `false`, если за это время началась остановка
+    async fn tick(&self, period: Duration) -> bool {
+        let mut stopping = self.stopping.subscribe();
+        tokio::select! {
+            _ = stopping.wait_for(|&stopping| stopping) => false,
+            _ = sleep(period) => true,
+        }
+    }
+
+    /// Выпускаем результат обработки в порядке публикации в источнике.
+    /// `ids` — все id, которые занимал пост (для альбома — каждая его часть)
+    async fn publish(&self, chat_id: i64, ids: Vec<i32>, date: i64, edit_date: Option<i64>, outcome: Outcome<T::Media>) {
+        let Some(chat) = self.chats.get(&chat_id) else {
+            return;
+        };
+        let deliveries = match outcome {
+            Outcome::Publish(deliveries) => Some(deliveries),
+            Outcome::Skip => {
+                self.finish(chat, chat_id, &ids).await;
+                None
+            }
+            Outcome::Failed(error) => {
+                self.dead_letter(chat, chat_id, &ids, &error).await;
+                None
+            }
+        };
+
+        // Держим буфер источника на время отправки, чтобы посты не обгоняли друг друга
+        let mut buffer = chat.ordering.lock().await;
+        // Результат кладём на место самого раннего id, остальные просто освобождаем
+        let first = ids.iter().copied().min();
+        let mut prepared = deliveries.map(|deliveries| Prepared {
+            chat_id,
+            ids: ids.clone(),
+            date,
+            edit_date,
+            deliveries,
+        });
+        for &id in &ids {
+            let value = if Some(id) == first { prepared.take() } else { None };
+            buffer.complete(id, value);
+        }
+
+        for prepared in buffer.drain_ready() {
+            match &self.cross_source {
+                Some(window) => {
+                    let (date, id) = (prepared.date, prepared.ids[0]);
+                    window.lock().await.push(date, chat_id, id, prepared);
+                }
+                None => self.deliver(prepared).await,
+            }
+        }
+    }
+
+    /// Режим cross_source: выпускаем посты всех источников по времени публикации.
+    /// При остановке окно целиком выпускает [`Self::shutdown`]
+    async fn flush_window(self: Arc<Self>) {
+        let Some(window) = &self.cross_source else {
+            return;
+        };
+        while self.tick(WINDOW_TICK).await {
+            let released = window.lock().await.drain_expired();
+            for prepared in released {
+                self.deliver(prepared).await;
+            }
+        }
+    }
+
+    /// Отправляем во все target поста. Target, куда пост уже ушёл
+    /// до перезапуска или неудачи в другом target, пропускаем
+    async fn deliver(&self, prepared: Prepared<T::Media>) {
+        let Prepared {
+            chat_id,
+            ids,
+            edit_date,
+            deliveries,
+            ..
+        } = prepared;
+        let Some(chat) = self.chats.get(&chat_id) else {
+            return;
+        };
+
+        for delivery in &deliveries {
+            if chat.outbox.lock().await.delivered(chat_id, ids[0]).contains(&delivery.target) {
+                continue;
+            }
+            if !self.deliver_to(chat, chat_id, &ids, edit_date, delivery).await {
+                return;
+            }
+        }
+
+        {
+            let mut outbox = chat.outbox.lock().await;
+            outbox.set_state(chat_id, &ids, OutboxState::Sent);
+            self.persist_outbox(&outbox, chat_id, &ids).await;
+        }
+        self.finish(chat, chat_id, &ids).await;
+    }
+
+    /// Отправляем в один target с повторами. FLOOD_WAIT попыткой не считается,
+    /// после [`TransportError::Permanent`] не повторяем.
+    /// `false`, если попытки исчерпаны и пост ушёл в dead letters
+    async fn deliver_to(
+        &self,
+        chat: &ChatState<T::Media>,
+        chat_id: i64,
+        ids: &[i32],
+        edit_date: Option<i64>,
+        delivery: &Delivery<T::Media>,
+    ) -> bool {
+        let target = delivery.target;
+        loop {
+            let error = match with_flood_wait(|| self.send(target, &delivery.outgoing)).await {
+                Ok(target_ids) => {
+                    {
+                        let mut outbox = chat.outbox.lock().await;
+                        outbox.mark_delivered(chat_id, ids, target);
+                        self.persist_outbox(&outbox, chat_id, ids).await;
+                    }
+                    self.record_mapping(chat, chat_id, ids, target, target_ids, edit_date).await;
+                    return true;
+                }
+                Err(e) => e,
+            };
+
+            log_warn!("Send of {:?} from {} to {} failed: {}", ids, chat_id, target, error);
+            let permanent = matches!(error, TransportError::Permanent(_));
+            let error = error.to_string();
+            match self.record_failure(chat, chat_id, ids, &error).await {
+                Some(delay) if !permanent => sleep(delay).await,
+                _ => {
+                    self.dead_letter(chat, chat_id, ids, &error).await;
+                    return false;
+                }
+            }
+        }
+    }
+
+    /// Пост дошёл до финала: переносим id из outbox в историю
+    async fn finish(&self, chat: &ChatState<T::Media>, chat_id: i64, ids: &[i32]) {
+        if ids.is_empty() {
+            return;
+        }
+        {
+            let mut history = chat.history.lock().await;
+            history.mark_processed(chat_id, ids);
+            history.trim(&self.retention);
+        }
+        if let Err(e) = self.storage.mark_processed(chat_id, ids).await {
+            log_error!("Error while saving messages history: {}", e);
+        }
+
+        let mut outbox = chat.outbox.lock().await;
+        outbox.remove(chat_id, ids);
+        self.persist_outbox(&outbox, chat_id, ids).await;
+    }
+
+    /// Запоминаем, какие сообщения в `target` соответствуют посту. Если число
+    /// сообщений совпадает (альбом), сопоставляем по порядку, иначе каждому id — все копии.
+    /// `edit_date` — правка оригинала, с которой опубликована копия
+    async fn record_mapping(
+        &self,
+        chat: &ChatState<T::Media>,
+        chat_id: i64,
+        ids: &[i32],
+        target: i64,
+        target_ids: Vec<i32>,
+        edit_date: Option<i64>,
+    ) {
+        let mut sorted = ids.to_vec();
+        sorted.sort_unstable();
+
+        for (index, &id) in sorted.iter().enumerate() {
+            let message_ids = if sorted.len() == target_ids.len() {
+                vec![target_ids[index]]
+            } else {
+                target_ids.clone()
+            };
+            let copy = TargetRef {
+                chat_id: target,
+                message_ids,
+                edit_date,
+            };
+
+            chat.history.lock().await.add_mapping(chat_id, id, copy.clone());
+            if let Err(e) = self.storage.record_mapping(chat_id, id, &copy).await {
+                log_error!("Error while saving message mapping: {}", e);
+            }
+        }
+    }
+
+    /// Отдаём хранилищу текущее состояние записей `ids` из outbox
+    async fn persist_outbox(&self, outbox: &Outbox, chat_id: i64, ids: &[i32]) {
+        let entries: Vec<(i32, Option<OutboxEntry>)> =
+            ids.iter().map(|&id| (id, outbox.get(chat_id, id).cloned())).collect();
+        if let Err(e) = self.storage.save_outbox(chat_id, &entries).await {
+            log_error!("Error while saving outbox: {}", e);
+        }
+    }
+
+    /// Задержка перед следующей попыткой, `None` — попытки исчерпаны
+    async fn record_failure(
+        &self,
+        chat: &ChatState<T::Media>,
+        chat_id: i64,
+        ids: &[i32],
+        error: &str,
+    ) -> Option<Duration> {
+        let mut outbox = chat.outbox.lock().await;
+        let delay = outbox.record_failure(chat_id, ids, error, &self.retry);
+        self.persist_outbox(&outbox, chat_id, ids).await;
+        delay
+    }
+
+    async fn dead_letter(&self, chat: &ChatState<T::Media>, chat_id: i64, ids: &[i32], error: &str) {
+        log_error!("Posts {:?} from {} moved to dead letters: {}", ids, chat_id, error);
+        let mut outbox = chat.outbox.lock().await;
+        outbox.set_state(chat_id, ids, OutboxState::Failed);
+        self.persist_outbox(&outbox, chat_id, ids).await;
+    }
+
+    /// Маршруты источника, сгруппированные по профилю промпта:
+    /// модель спрашиваем один раз на профиль, а не на маршрут
+    fn route_groups(&self, chat_id: i64) -> Vec<(&Arc<PromptProfile>, Vec<&Route>)> {
+        let mut groups: Vec<(&Arc<PromptProfile>, Vec<&Route>)> = Vec::new();
+        for route in self.routes.iter().filter(|route| route.accepts_source(chat_id)) {
+            let prompt = self.prompts.for_route(route, chat_id);
+            match groups.iter_mut().find(|(profile, _)| profile.name == prompt.name) {
+                Some((_, routes)) => routes.push(route),
+                None => groups.push((prompt, vec![route])),
+            }
+        }
+        groups
+    }
+
+    /// Решение по посту для профиля `prompt`: из outbox, если модель уже отвечала,
+    /// иначе спрашиваем её с повторами. Решение сохраняется на все `ids` поста.
+    /// Если ответ так и не разобран, `Err`: пост уходит в dead letters, а не пропускается.
+    /// Правку (`edited`) опубликованного поста outbox не касается: модель спрашиваем
+    /// один раз, при ошибке правку повторит следующая сверка
+    #[allow(clippy::too_many_arguments)]
+    async fn decide(
+        &self,
+        chat_id: i64,
+        ids: &[i32],
+        text: &str,
+        images: &PostImages<'_, T::Media>,
+        date: i64,
+        prompt: &PromptProfile,
+        edited: bool,
+    ) -> Result<Verdict> {
+        let chat = self.chats.get(&chat_id).ok_or("unknown source chat")?;
+        if !edited && let Some(decision) = chat.outbox.lock().await.decision(chat_id, ids[0], &prompt.name) {
+            return Ok(decision);
+        }
+
+        let system_prompt = prompt.render(&chat.title, date);
+        let images = self.post_images(images).await;
+        loop {
+            match self.classifier.classify(&system_prompt, text, images).await {
+                Ok(answer) => {
+                    let decision = answer.verdict;
+                    if !edited {
+                        let mut outbox = chat.outbox.lock().await;
+                        outbox.set_decision(chat_id, ids, &prompt.name, &decision);
+                        self.persist_outbox(&outbox, chat_id, ids).await;
+                    }
+
+                    let record = Decision {
+                        category: Some(decision.category.clone()),
+                        text: Some(decision.text.clone()),
+                        model: answer.model,
+                    };
+                    for &id in ids {
+                        if let Err(e) = self.storage.record_decision(chat_id, id, &record).await {
+                            log_error!("Error while saving decision: {}", e);
+                        }
+                    }
+                    return Ok(decision);
+                }
+                Err(e) => {
+                    log_warn!("Classification of {:?} from {} failed: {}", ids, chat_id, e);
+                    if edited {
+                        return Err(e);
+                    }
+                    match self.record_failure(chat, chat_id, ids, &e.to_string()).await {
+                        Some(delay) => sleep(delay).await,
+                        None => return Err(e),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Картинки поста, скачанные один раз на все профили промпта
+    async fn post_images<'a>(&self, images: &'a PostImages<'_, T::Media>) -> &'a [Image] {
+        images.loaded.get_or_init(|| self.download_images(&images.media)).await
+    }
+
+    /// Не больше `images.max_per_post` картинок. Ошибка скачивания не мешает
+    /// классификации: модель просто не увидит эту картинку
+    async fn download_images(&self, media: &[&T::Media]) -> Vec<Image> {
+        let mut images = Vec::new();
+        if !self.images.enabled {
+            return images;
+        }
+        for media in media {
+            if images.len() >= self.images.max_per_post {
+                break;
+            }
+            match self.transport.download_image(media, self.images.max_bytes).await {
+                Ok(Some(file)) => images.push(Image {
+                    mime: file.mime,
+                    data: file.bytes.into(),
+                }),
+                Ok(None) => {}
+                Err(e) => log_warn!("Failed to download an image: {}", e),
+            }
+        }
+        images
+    }
+
+    /// Доставки поста по маршрутам. `edited` — правка уже опубликованного поста, см. [`Self::decide`]
+    async fn prepare_single(&self, post: SourcePost<T::Media>, edited: bool) -> Result<Option<Vec<Delivery<T::Media>>>> {
+        if post.kind == PostKind::Unsupported {
+            log_debug!("Post {} from {} is of an unsupported kind", post.id, post.chat_id);
+            return Ok(None);
+        }
+
+        // Модель и фильтры маршрутов видят и описание вложения: вопрос опроса, имя файла...
+        let markup = post.kind.classification_text(&post.markup);
+        let text = post.kind.classification_text(&post.text);
+        // Пост без текста оцениваем по картинке, без картинки — пропускаем
+        let images = PostImages::new(&post.media);
+        if text.is_empty() && self.post_images(&images).await.is_empty() {
+            return Ok(None);
+        }
+
+        let mut deliveries: Vec<Delivery<T::Media>> = Vec::new();
+        for (prompt, routes) in self.route_groups(post.chat_id) {
+            let verdict = self
+                .decide(post.chat_id, &[post.id], &markup, &images, post.date, prompt, edited)
+                .await?;
+            for route in routing::targets(routes, post.chat_id, &verdict, &text) {
+                if deliveries.iter().any(|delivery| delivery.target == route.target) {
+                    continue;
+                }
+                if route.mode == RepostMode::Forward {
+                    deliveries.push(Delivery {
+                        target: route.target,
+                        outgoing: OutgoingPost::Forward {
+                            chat_id: post.chat_id,
+                            ids: vec![post.id],
+                        },
+                    });
+                    continue;
+                }
+                let published = match post.kind.text_limit() {
+                    // У поста без текста оставляем пустую подпись оригинала, что бы ни ответила модель
+                    Some(_) if post.text.trim().is_empty() => post.markup.clone(),
+                    Some(limit) => {
+                        let rewrite = publish_text(&verdict.text, &post.markup, &[&post.text, &text, &markup]);
+                        self.fit(route, rewrite, limit).await
+                    }
+                    None => String::new(),
+                };
+                let Some(outgoing) = post.kind.outgoing(published, post.media.clone()) else {
+                    continue;
+                };
+                deliveries.push(Delivery {
+                    target: route.target,
+                    outgoing,
+                });
+            }
+        }
+
+        Ok((!deliveries.is_empty()).then_some(deliveries))
+    }
+
+    /// Альбом классифицируется один раз по всем подписям вместе. Ответ модели
+    /// становится подписью первого элемента, остальные — по `album_captions`
+    async fn prepare_album(
+        &self,
+        mut posts: Vec<SourcePost<T::Media>>,
+        edited: bool,
+    ) -> Result<Option<Vec<Delivery<T::Media>>>> {
+        posts.sort_by_key(|post| post.id);
+        let Some(first) = posts.first() else {
+            return Ok(None);
+        };
+        let (chat_id, date) = (first.chat_id, first.date);
+        if posts.iter().all(|post| post.media.is_none()) {
+            return Ok(None);
+        }
+
+        let ids: Vec<i32> = posts.iter().map(|post| post.id).collect();
+        let join_captions = |caption: fn(&SourcePost<T::Media>) -> String| {
+            posts
+                .iter()
+                .map(caption)
+                .filter(|caption| !caption.is_empty())
+                .collect::<Vec<_>>()
+                .join("\n\n")
+        };
+        // Подписи как есть — для публикации, с описанием вложений — для модели и маршрутов
+        let captions = join_captions(|post| post.markup.clone());
+        let markup = join_captions(|post| post.kind.classification_text(&post.markup));
+        let text = join_captions(|post| post.kind.classification_text(&post.text));
+        let images = PostImages::new(posts.iter().filter_map(|post| post.media.as_ref()));
+        if text.is_empty() && self.post_images(&images).await.is_empty() {
+            return Ok(None);
+        }
+
+        let mut deliveries: Vec<Delivery<T::Media>> = Vec::new();
+        for (prompt, routes) in self.route_groups(chat_id) {
+            let verdict = self.decide(chat_id, &ids, &markup, &images, date, prompt, edited).await?;
+            for route in routing::targets(routes, chat_id, &verdict, &text) {
+                if deliveries.iter().any(|delivery| delivery.target == route.target) {
+                    continue;
+                }
+                if route.mode == RepostMode::Forward {
+                    deliveries.push(Delivery {
+                        target: route.target,
+                        outgoing: OutgoingPost::Forward {
+                            chat_id,
+                            ids: ids.clone(),
+                        },
+                    });
+                    continue;
+                }
+                // Альбом без подписей так и публикуем, что бы ни ответила модель
+                let caption = if captions.trim().is_empty() {
+                    captions.clone()
+                } else {
+                    let rewrite = publish_text(&verdict.text, &captions, &[&text, &markup]);
+                    self.fit(route, rewrite, CAPTION_LIMIT).await
+                };
+                let items = posts
+                    .iter()
+                    .filter_map(|post| post.media.clone().map(|media| (post, media)))
+                    .enumerate()
+                    .map(|(index, (post, media))| AlbumItem {
+                        caption: match (index, self.album_captions) {
+                            (0, _) => caption.clone(),
+                            (_, AlbumCaptions::Keep) => post.markup.clone(),
+                            (_, AlbumCaptions::Clear) => String::new(),
+                        },
+                        media,
+                    })
+                    .collect();
+                deliveries.push(Delivery {
+                    target: route.target,
+                    outgoing: OutgoingPost::Album(items),
+                });
+            }
+        }
+
+        Ok((!deliveries.is_empty()).then_some(deliveries))
+    }
+
+    /// Текст длиннее `limit` маршрут с `overflow: shorten` отдаёт модели сократить.
+    /// Если не вышло, текст остаётся как есть и делится при отправке
+    async fn fit(&self, route: &Route, text: String, limit: usize) -> String {
+        if route.overflow != Overflow::Shorten || self.transport.text_len(&text) <= limit {
+            return text;
+        }
+        match self.classifier.shorten(&text, limit).await {
+            Ok(shortened) if self.transport.text_len(&shortened) <= limit => shortened,
+            Ok(_) => {
+                log_warn!("Shortened post is still longer than {}, it will be split", limit);
+                text
+            }
+            Err(e) => {
+                log_warn!("Failed to shorten a post, it will be split: {}", e);
+                text
+            }
+        }
+    }
+
+    /// Отправляем в `target`, возвращаем id отправленных сообщений.
+    /// Текст сверх лимита Telegram уходит следующими сообщениями ответом на первое
+    async fn send(&self, target: i64, outgoing: &OutgoingPost<T::Media>) -> std::result::Result<Vec<i32>, TransportError> {
+        let (outgoing, overflow) = self.split_overflow(outgoing);
+        let mut sent = match outgoing {
+            OutgoingPost::Text { text, link_preview } => {
+                vec![self.transport.send_text(target, &text, None, link_preview).await?]
+            }
+            OutgoingPost::Media { media, caption } => vec![self.transport.send_media(target, &media, &caption).await?],
+            OutgoingPost::Album(items) => {
+                let sent = self.transport.send_album(target, items).await?;
+                log_debug!("Album of {} sent to {}", sent.len(), target);
+                sent
+            }
+            OutgoingPost::Forward { chat_id, ids } => self.transport.forward(target, chat_id, &ids).await?,
+        };
+
+        let reply_to = sent.first().copied();
+        for part in overflow {
+            sent.push(self.transport.send_text(target, &part, reply_to, false).await?);
+        }
+        Ok(sent)
+    }
+
+    /// Приводим опубликованную копию `message_ids` к `outgoing`, возвращаем id её сообщений.
+    /// Лишние продолжения удаляются, недостающие отправляются ответом. Пересланные
+    /// посты Telegram править не даёт, они остаются как есть
+    async fn update(
+        &self,
+        target: i64,
+        message_ids: &[i32],
+        outgoing: &OutgoingPost<T::Media>,
+    ) -> std::result::Result<Vec<i32>, TransportError> {
+        let Some(&first) = message_ids.first() else {
+            // Копия была удалена прошлой правкой, публикуем заново
+            return self.send(target, outgoing).await;
+        };
+        let (outgoing, overflow) = self.split_overflow(outgoing);
+        let mut kept = match outgoing {
+            OutgoingPost::Text { text, link_preview } => {
+                self.transport.edit_text(target, first, &text, link_preview).await?;
+                vec![first]
+            }
+            OutgoingPost::Media { media, caption } => {
+                self.transport.edit_media(target, first, &media, &caption).await?;
+                vec![first]
+            }
+            OutgoingPost::Album(items) => {
+                let mut kept = Vec::new();
+                for (item, &id) in items.iter().zip(message_ids) {
+                    self.transport.edit_media(target, id, &item.media, &item.caption).await?;
+                    kept.push(id);
+                }
+                kept
+            }
+            OutgoingPost::Forward { .. } => return Ok(message_ids.to_vec()),
+        };
+
+        let old_overflow = &message_ids[kept.len()..];
+        for (index, part) in overflow.iter().enumerate() {
+            match old_overflow.get(index) {
+                Some(&id) => {
+                    self.transport.edit_text(target, id, part, false).await?;
+                    kept.push(id);
+                }
+                None => kept.push(self.transport.send_text(target, part, Some(first), false).await?),
+            }
+        }
+        if old_overflow.len() > overflow.len() {
+            self.transport.delete(target, &old_overflow[overflow.len()..]).await?;
+        }
+        Ok(kept)
+    }
+
+    /// Режем тексты поста по лимитам Telegram: пост с первыми частями и остаток
+    fn split_overflow(&self, outgoing: &OutgoingPost<T::Media>) -> (OutgoingPost<T::Media>, Vec<String>) {
+        let mut outgoing = outgoing.clone();
+        let mut overflow = Vec::new();
+        let mut split = |text: &mut String, first_limit: usize| {
+            let mut parts = self.transport.split_text(text, first_limit, TEXT_LIMIT).into_iter();
+            *text = parts.next().unwrap_or_default();
+            overflow.extend(parts);
+        };
+        match &mut outgoing {
+            OutgoingPost::Text { text, .. } => split(text, TEXT_LIMIT),
+            OutgoingPost::Media { caption, .. } => split(caption, CAPTION_LIMIT),
+            OutgoingPost::Album(items) => {
+                for item in items {
+                    split(&mut item.caption, CAPTION_LIMIT);
+                }
+            }
+            OutgoingPost::Forward { .. } => {}
+        }
+        (outgoing, overflow)
+    }
+}
+
+/// Повторяем `operation`, пока Telegram просит подождать (FLOOD_WAIT)
+async fn with_flood_wait<R, F, Fut>(mut operation: F) -> std::result::Result<R, TransportError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = std::result::Result<R, TransportError>>,
+{
+    loop {
+        match operation().await {
+            Err(TransportError::FloodWait(secs)) => {
+                log_info!("Wait... {:?}", secs);
+                sleep(Duration::from_secs(secs.into())).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Опубликованные копии постов `ids` по target. У альбома каждой части
+/// соответствует своё сообщение, их собираем вместе
+fn copies_by_target(history: &History, chat_id: i64, ids: &[i32]) -> Vec<(i64, Vec<i32>)> {
+    let mut copies: Vec<(i64, Vec<i32>)> = Vec::new();
+    for &id in ids {
+        for copy in history.copies(chat_id, id) {
+            match copies.iter_mut().find(|(target, _)| *target == copy.chat_id) {
+                Some((_, message_ids)) => message_ids.extend(&copy.message_ids),
+                None => copies.push((copy.chat_id, copy.message_ids.clone())),
+            }
+        }
+    }
+    for (_, message_ids) in &mut copies {
+        message_ids.sort_unstable();
+        message_ids.dedup();
+    }
+    copies
+}
+
+/// Текст для публикации. Если модель вернула пост без изменений (`markup`
+/// или один из вариантов `originals`, что она видела), публикуем оригинал с его форматированием
+fn publish_text(rewrite: &str, markup: &str, originals: &[&str]) -> String {
+    let rewrite = rewrite.trim();
+    if rewrite == markup.trim() || originals.iter().any(|original| rewrite == original.trim()) {
+        markup.to_string()
+    } else {
+        rewrite.to_string()
+    }
+}
+
+/// Дожидаемся задачи обработки. Ошибка или паника отправляют пост в dead letters,
+/// но место в очереди всё равно освобождается
+async fn settle<M>(task: JoinHandle<Result<Option<Vec<Delivery<M>>>>>) -> Outcome<M> {
+    match task.await {
+        Ok(Ok(Some(deliveries))) => Outcome::Publish(deliveries),
+        Ok(Ok(None)) => Outcome::Skip,
+        Ok(Err(e)) => Outcome::Failed(e.to_string()),
+        Err(e) => Outcome::Failed(format!("processing task failed: {}", e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, path::PathBuf};
+
+    use super::*;
+    use crate::{
+        classifier::RELEVANT,
+        config::RouteConfig,
+        llm::fake::FakeProvider,
+        storage::json::JsonStorage,
+        transport::{
+            fake::{FakeTransport, SentPost},
+            MediaFile,
+        },
+    };
+
+    const SOURCE: i64 = 1;
+    const TARGET: i64 = 100;
+
+    /// Модель: «реклама» в посте — реклама, «мусор» — ответ без JSON,
+    /// остальное релевантно и публикуется без изменений
+    fn model() -> Arc<FakeProvider> {
+        FakeProvider::new("fake", |text| {
+            if text.contains("мусор") {
+                return Ok("Не знаю, что ответить".to_string());
+            }
+            let status = if text.contains("реклама") { "реклама" } else { RELEVANT };
+            Ok(serde_json::json!({ "status": status, "text": text }).to_string())
+        })
+    }
+
+    /// Быстрые повторы, альбомы и без периодической сверки
+    fn settings() -> BotSettings {
+        BotSettings {
+            album_timeout_ms: 100,
+            outbox: OutboxConfig {
+                max_attempts: 2,
+                backoff_base_secs: 0,
+                backoff_max_secs: 0,
+            },
+            sync: SyncConfig {
+                check_interval_secs: 0,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    fn now() -> i64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
+    }
+
+    fn text_post(id: i32, text: &str) -> SourcePost<String> {
+        SourcePost {
+            chat_id: SOURCE,
+            id,
+            grouped_id: None,
+            date: now(),
+            edit_date: None,
+            text: text.to_string(),
+            markup: text.to_string(),
+            kind: PostKind::Text,
+            media: None,
+        }
+    }
+
+    fn photo_post(id: i32, grouped_id: Option<i64>, caption: &str, media: &str) -> SourcePost<String> {
+        SourcePost {
+            grouped_id,
+            kind: PostKind::Photo,
+            media: Some(media.to_string()),
+            ..text_post(id, caption)
+        }
+    }
+
+    fn sent_text(text: &str) -> SentPost {
+        SentPost::Text {
+            chat_id: TARGET,
+            text: text.to_string(),
+            reply_to: None,
+            link_preview: false,
+        }
+    }
+
+    /// Ждём, пока `condition` не выполнится
+    async fn eventually(mut condition: impl FnMut() -> bool) {
+        let waiting = async {
+            while !condition() {
+                sleep(Duration::from_millis(10)).await;
+            }
+        };
+        timeout(Duration::from_secs(5), waiting).await.expect("condition is not met in 5 seconds");
+    }
+
+    struct Harness {
+        transport: Arc<FakeTransport>,
+        pipeline: Arc<Pipeline<FakeTransport>>,
+    }
+
+    impl Harness {
+        /// Запускаем конвейер с одним источником и хранилищем в пустом каталоге `name`
+        async fn start(name: &str, provider: Arc<FakeProvider>, route: RouteConfig, settings: BotSettings) -> Self {
+            let dir: PathBuf = std::env::temp_dir().join(format!("zad-pipeline-{}-{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            let storage = JsonStorage::open(
+                dir.join("history.json"),
+                dir.join("journal.jsonl"),
+                dir.join("outbox.json"),
+                Retention::default(),
+                100,
+            )
+            .await
+            .unwrap();
+
+            let transport = Arc::new(FakeTransport::new());
+            transport.add_chat("source", SOURCE, "Источник");
+            let source = transport.resolve_username("source").await.unwrap().unwrap();
+            let prompts = Prompts {
+                default: PromptProfile::load_default(&Default::default()).await.unwrap(),
+                sources: HashMap::new(),
+            };
+            let pipeline = Pipeline::new(
+                Arc::clone(&transport),
+                Classifier::new(vec![provider], 4, 0),
+                Arc::new(storage),
+                Retention::default(),
+                vec![source],
+                vec![Route::new(&route, TARGET, HashSet::new(), None)],
+                prompts,
+                &settings,
+            )
+            .await
+            .unwrap();
+
+            let pipeline = Arc::new(pipeline);
+            let supervisor = Supervisor::new(settings.supervisor.clone());
+            tokio::spawn(Arc::clone(&pipeline).run(supervisor));
+            Self { transport, pipeline }
+        }
+
+        fn chat(&self) -> &ChatState<String> {
+            &self.pipeline.chats[&SOURCE]
+        }
+
+        /// Ждём `count` отправок и возвращаем всё отправленное
+        async fn sent(&self, count: usize) -> Vec<SentPost> {
+            eventually(|| self.transport.sent().len() >= count).await;
+            self.transport.sent()
+        }
+
+        /// Ждём, пока пост дойдёт до истории и уйдёт из outbox
+        async fn processed(&self, id: i32) {
+            eventually(|| {
+                let processed = self.chat().history.try_lock().is_ok_and(|history| history.is_processed(SOURCE, id));
+                processed && self.chat().outbox.try_lock().is_ok_and(|outbox| outbox.get(SOURCE, id).is_none())
+            })
+            .await;
+        }
+
+        /// Ждём, пока пост попадёт в dead letters
+        async fn dead_letter(&self, id: i32) {
+            eventually(|| {
+                self.chat().outbox.try_lock().is_ok_and(|outbox| {
+                    outbox.get(SOURCE, id).is_some_and(|entry| entry.state == OutboxState::Failed)
+                })
+            })
+            .await;
+        }
+    }
+
+    #[tokio::test]
+    async fn publishes_relevant_posts_only() {
+        let harness = Harness::start("publish", model(), RouteConfig::default(), settings()).await;
+
+        harness.transport.push_post(text_post(1, "Это реклама"));
+        harness.transport.push_post(text_post(2, "Пост"));
+
+        assert_eq!(harness.sent(1).await, [sent_text("Пост")]);
+        harness.processed(1).await;
+        harness.processed(2).await;
+        assert_eq!(harness.chat().history.lock().await.copies(SOURCE, 2)[0].message_ids, [1]);
+    }
+
+    #[tokio::test]
+    async fn publishes_in_source_order() {
+        let provider = model();
+        provider.delay_on("Первый", Duration::from_millis(300));
+        let harness = Harness::start("order", provider, RouteConfig::default(), settings()).await;
+
+        harness.transport.push_post(text_post(1, "Первый пост"));
+        harness.transport.push_post(text_post(2, "Это реклама"));
+        harness.transport.push_post(text_post(3, "Третий пост"));
+        harness.processed(3).await;
+
+        // Третий пост готов раньше первого, но ждёт его, пропущенная реклама не задерживает
+        assert_eq!(harness.transport.sent(), [sent_text("Первый пост"), sent_text("Третий пост")]);
+    }
+
+    #[tokio::test]
+    async fn retries_failed_send() {
+        let harness = Harness::start("retry", model(), RouteConfig::default(), settings()).await;
+        harness.transport.fail_next_send(TransportError::Other("connection reset".to_string()));
+
+        harness.transport.push_post(text_post(1, "Пост"));
+        harness.processed(1).await;
+
+        assert_eq!(harness.transport.sent(), [sent_text("Пост")]);
+        assert_eq!(harness.chat().history.lock().await.copies(SOURCE, 1)[0].message_ids, [1]);
+    }
+
+    #[tokio::test]
+    async fn exhausted_send_goes_to_dead_letters() {
+        let harness = Harness::start("dead-send", model(), RouteConfig::default(), settings()).await;
+        for _ in 0..2 {
+            harness.transport.fail_next_send(TransportError::Rpc("CHAT_WRITE_FORBIDDEN".to_string()));
+        }
+
+        harness.transport.push_post(text_post(1, "Первый пост"));
+        harness.transport.push_post(text_post(2, "Второй пост"));
+        harness.dead_letter(1).await;
+        harness.processed(2).await;
+
+        // Пост в dead letters не держит очередь источника
+        assert_eq!(harness.transport.sent(), [sent_text("Второй пост")]);
+        let outbox = harness.chat().outbox.lock().await;
+        assert_eq!(outbox.get(SOURCE, 1).unwrap().attempts, 2);
+    }
+
+    #[tokio::test]
+    async fn unparseable_answer_goes_to_dead_letters() {
+        let provider = model();
+        let harness = Harness::start("dead-answer", Arc::clone(&provider), RouteConfig::default(), settings()).await;
+
+        harness.transport.push_post(text_post(1, "Какой-то мусор"));
+        harness.transport.push_post(text_post(2, "Второй пост"));
+        harness.dead_letter(1).await;
+        harness.processed(2).await;
+
+        assert_eq!(harness.transport.sent(), [sent_text("Второй пост")]);
+        // Обе попытки по первому посту и одна по второму
+        assert_eq!(provider.calls(), 3);
+        assert!(!harness.chat().history.lock().await.is_processed(SOURCE, 1));
+    }
+
+    #[tokio::test]
+    async fn assembles_album() {
+        let harness = Harness::start("album", model(), RouteConfig::default(), settings()).await;
+
+        harness.transport.push_post(photo_post(1, Some(77), "Фото с конференции", "a"));
+        harness.transport.push_post(photo_post(2, Some(77), "", "b"));
+        harness.transport.push_post(photo_post(3, Some(77), "", "c"));
+        harness.transport.push_post(text_post(4, "После альбома"));
+        harness.processed(4).await;
+
+        let album = SentPost::Album {
+            chat_id: TARGET,
+            items: vec![
+                ("Фото с конференции".to_string(), "a".to_string()),
+                (String::new(), "b".to_string()),
+                (String::new(), "c".to_string()),
+            ],
+        };
+        assert_eq!(harness.transport.sent(), [album, sent_text("После альбома")]);
+        let history = harness.chat().history.lock().await;
+        assert_eq!(copies_by_target(&history, SOURCE, &[1, 2, 3]), [(TARGET, vec![1, 2, 3])]);
+    }
+
+    #[tokio::test]
+    async fn image_only_post_keeps_empty_caption() {
+        let harness = Harness::start("image-only", model(), RouteConfig::default(), settings()).await;
+        let image = MediaFile {
+            mime: "image/jpeg".to_string(),
+            bytes: vec![0xff, 0xd8],
+        };
+        harness.transport.add_image("photo", image);
+
+        harness.transport.push_post(photo_post(1, None, "", "photo"));
+
+        // Модель описала картинку, но подпись остаётся пустой, как у оригинала
+        let media = SentPost::Media {
+            chat_id: TARGET,
+            media: "photo".to_string(),
+            caption: String::new(),
+        };
+        assert_eq!(harness.sent(1).await, [media]);
+    }
+
+    #[tokio::test]
+    async fn shutdown_flushes_pending_album() {
+        let settings = BotSettings {
+            album_timeout_ms: 60_000,
+            ..settings()
+        };
+        let harness = Harness::start("shutdown", model(), RouteConfig::default(), settings).await;
+
+        harness.transport.push_post(photo_post(1, Some(77), "Альбом", "a"));
+        harness.transport.push_post(photo_post(2, Some(77), "", "b"));
+        let groups = &harness.pipeline.media_groups.groups;
+        eventually(|| groups.try_lock().is_ok_and(|groups| groups.get(&77).is_some_and(|posts| posts.len() == 2))).await;
+        harness.pipeline.shutdown(Duration::from_secs(5)).await;
+
+        assert!(matches!(&harness.transport.sent()[..], [SentPost::Album { items, .. }] if items.len() == 2));
+        assert!(harness.chat().history.lock().await.is_processed(SOURCE, 2));
+    }
+
+    #[tokio::test]
+    async fn forward_mode_forwards_original() {
+        let route = RouteConfig {
+            mode: RepostMode::Forward,
+            ..Default::default()
+        };
+        let harness = Harness::start("forward", model(), route, settings()).await;
+
+        harness.transport.push_post(text_post(5, "Пост"));
+
+        let forward = SentPost::Forward {
+            chat_id: TARGET,
+            from_chat_id: SOURCE,
+            ids: vec![5],
+        };
+        assert_eq!(harness.sent(1).await, [forward]);
+    }
+
+    #[tokio::test]
+    async fn edit_is_applied_to_copy() {
+        let harness = Harness::start("edit", model(), RouteConfig::default(), settings()).await;
+        harness.transport.push_post(text_post(1, "Старый текст"));
+        harness.processed(1).await;
+
+        let edit_date = now();
+        harness.transport.edit_post(SourcePost {
+            edit_date: Some(edit_date),
+            ..text_post(1, "Новый текст")
+        });
+
+        let edit = SentPost::EditText {
+            chat_id: TARGET,
+            id: 1,
+            text: "Новый текст".to_string(),
+            link_preview: false,
+        };
+        assert_eq!(harness.sent(2).await[1], edit);
+        eventually(|| {
+            harness.chat().history.try_lock().is_ok_and(|history| {
+                history.copies(SOURCE, 1).first().is_some_and(|copy| copy.edit_date == Some(edit_date))
+            })
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn edit_that_no_longer_fits_removes_copy() {
+        let harness = Harness::start("edit-remove", model(), RouteConfig::default(), settings()).await;
+        harness.transport.push_post(text_post(1, "Пост"));
+        harness.processed(1).await;
+
+        harness.transport.edit_post(SourcePost {
+            edit_date: Some(now()),
+            ..text_post(1, "Теперь это реклама")
+        });
+
+        let delete = SentPost::Delete {
+            chat_id: TARGET,
+            ids: vec![1],
+        };
+        assert_eq!(harness.sent(2).await[1], delete);
+    }
+
+    #[tokio::test]
+    async fn deletion_follows_on_delete() {
+        let cases = [
+            (
+                OnDelete::Delete,
+                SentPost::Delete {
+                    chat_id: TARGET,
+                    ids: vec![1],
+                },
+            ),
+            (
+                OnDelete::Mark,
+                SentPost::Text {
+                    chat_id: TARGET,
+                    text: "Удалено".to_string(),
+                    reply_to: Some(1),
+                    link_preview: false,
+                },
+            ),
+        ];
+        for (on_delete, expected) in cases {
+            let route = RouteConfig {
+                on_delete,
+                deleted_notice: "Удалено".to_string(),
+                ..Default::default()
+            };
+            let name = format!("delete-{:?}", on_delete);
+            let harness = Harness::start(&name, model(), route, settings()).await;
+            harness.transport.push_post(text_post(1, "Пост"));
+            harness.processed(1).await;
+
+            harness.transport.delete_posts(SOURCE, &[1]);
+
+            assert_eq!(harness.sent(2).await[1], expected, "{:?}", on_delete);
+            eventually(|| harness.chat().history.try_lock().is_ok_and(|history| history.copies(SOURCE, 1).is_empty())).await;
+        }
+    }
+}
@@ -0,0 +1,192 @@
+//! Шаблоны системного промпта классификатора.
+//!
+//! Шаблон — текстовый файл с подстановками `{keywords}`, `{stop_words}`,
+//! `{source_title}` и `{date}`. Слова подставляются при загрузке, данные
+//! поста — перед каждым запросом. Шаблоны проверяются при старте: неизвестная
+//! подстановка или пустой файл останавливают запуск.
+
+use std::{collections::HashMap, sync::Arc};
+
+use chrono::DateTime;
+use tokio::fs;
+
+use crate::{config::PromptOverride, routing::Route};
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Встроенный шаблон, если в конфиге не указан файл
+pub const DEFAULT_TEMPLATE: &str = include_str!("../prompts/classifier.txt");
+
+pub const DEFAULT_KEYWORDS: &[&str] = &[
+    "ИИ",
+    "Нейросети",
+    "нейоронные сети",
+    "утилита",
+    "утилиты",
+    "сервис",
+    "модель",
+    "OpenAI",
+    "Google",
+    "Mistral",
+    "Gemini",
+    "ChatGPT",
+    "GPT",
+    "DeepSeek",
+    "Grok",
+    "Elon Musk",
+    "технологии",
+    "мемы",
+];
+
+pub const DEFAULT_STOP_WORDS: &[&str] = &["политика", "война", "пропоганда", "погода", "новости не касаюшиеся ИИ"];
+
+const PLACEHOLDERS: [&str; 4] = ["keywords", "stop_words", "source_title", "date"];
+
+/// Промпт с подставленными словами
+#[derive(Debug)]
+pub struct PromptProfile {
+    /// Имя профиля, под ним решения модели кэшируются в outbox
+    pub name: String,
+    template: String,
+    keywords: Vec<String>,
+    stop_words: Vec<String>,
+}
+
+impl PromptProfile {
+    /// Профиль по умолчанию: поля, не заданные в `settings`, берём встроенные
+    pub async fn load_default(settings: &PromptOverride) -> Result<Arc<Self>> {
+        let base = Self {
+            name: "default".to_string(),
+            template: DEFAULT_TEMPLATE.to_string(),
+            keywords: DEFAULT_KEYWORDS.iter().map(|word| word.to_string()).collect(),
+            stop_words: DEFAULT_STOP_WORDS.iter().map(|word| word.to_string()).collect(),
+        };
+        base.with_override("default", settings).await
+    }
+
+    /// Профиль-переопределение: не заданные поля наследуются от `self`
+    pub async fn with_override(&self, name: &str, settings: &PromptOverride) -> Result<Arc<Self>> {
+        let template = match &settings.template {
+            Some(path) => fs::read_to_string(path)
+                .await
+                .map_err(|e| format!("prompt {}: failed to read {}: {}", name, path, e))?,
+            None => self.template.clone(),
+        };
+        validate(&template).map_err(|e| format!("prompt {}: {}", name, e))?;
+
+        Ok(Arc::new(Self {
+            name: name.to_string(),
+            template,
+            keywords: settings.keywords.clone().unwrap_or_else(|| self.keywords.clone()),
+            stop_words: settings.stop_words.clone().unwrap_or_else(|| self.stop_words.clone()),
+        }))
+    }
+
+    /// Собираем промпт для поста из канала `source_title`, опубликованного в `date` (unix time)
+    pub fn render(&self, source_title: &str, date: i64) -> String {
+        let date = DateTime::from_timestamp(date, 0)
+            .map(|date| date.format("%Y-%m-%d %H:%M UTC").to_string())
+            .unwrap_or_default();
+
+        self.template
+            .replace("{keywords}", &self.keywords.join(", "))
+            .replace("{stop_words}", &self.stop_words.join(", "))
+            .replace("{source_title}", source_title)
+            .replace("{date}", &date)
+    }
+}
+
+/// Подстановка — имя из латинских букв и `_` в фигурных скобках.
+/// JSON пример в шаблоне под это не попадает
+fn validate(template: &str) -> std::result::Result<(), String> {
+    if template.trim().is_empty() {
+        return Err("template is empty".to_string());
+    }
+
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        rest = &rest[start + 1..];
+        let Some(end) = rest.find('}') else {
+            break;
+        };
+        let name = &rest[..end];
+        let is_placeholder = !name.is_empty() && name.chars().all(|c| c.is_ascii_alphabetic() || c == '_');
+        if is_placeholder && !PLACEHOLDERS.contains(&name) {
+            return Err(format!(
+                "unknown placeholder {{{}}}, supported: {}",
+                name,
+                PLACEHOLDERS.map(|name| format!("{{{}}}", name)).join(", ")
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Профили промпта источников. Профиль маршрута хранится в самом маршруте
+pub struct Prompts {
+    pub default: Arc<PromptProfile>,
+    pub sources: HashMap<i64, Arc<PromptProfile>>,
+}
+
+impl Prompts {
+    /// Маршрут важнее источника, источник — профиля по умолчанию
+    pub fn for_route<'a>(&'a self, route: &'a Route, chat_id: i64) -> &'a Arc<PromptProfile> {
+        route
+            .prompt
+            .as_ref()
+            .or_else(|| self.sources.get(&chat_id))
+            .unwrap_or(&self.default)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_placeholders() {
+        let cases = [
+            ("Слова: {keywords}, стоп: {stop_words}", true),
+            ("Канал «{source_title}» {date}", true),
+            ("Формат: {\n    \"status\": \"релевантный\",\n    \"text\": \"...\"\n}", true),
+            ("Пустые скобки {} и {1}", true),
+            ("Незакрытая {keywords", true),
+            ("Тема: {topic}", false),
+            ("{keywords} и {Keywords}", false),
+            ("", false),
+            ("  \n\t", false),
+        ];
+        for (template, valid) in cases {
+            assert_eq!(validate(template).is_ok(), valid, "{:?}", template);
+        }
+    }
+
+    #[test]
+    fn unknown_placeholder_is_named_in_error() {
+        let error = validate("{keywords} {source}").unwrap_err();
+        assert!(error.contains("{source}"), "{}", error);
+        assert!(error.contains("{source_title}"), "{}", error);
+    }
+
+    #[test]
+    fn default_template_is_valid() {
+        assert_eq!(validate(DEFAULT_TEMPLATE), Ok(()));
+    }
+
+    #[tokio::test]
+    async fn override_inherits_and_renders() {
+        let base = PromptProfile::load_default(&PromptOverride::default()).await.unwrap();
+        let settings = PromptOverride {
+            keywords: Some(vec!["Rust".to_string(), "Tokio".to_string()]),
+            ..Default::default()
+        };
+        let profile = base.with_override("rust", &settings).await.unwrap();
+
+        assert_eq!(profile.name, "rust");
+        assert_eq!(profile.stop_words, base.stop_words);
+        let prompt = profile.render("Новости", 0);
+        assert!(prompt.contains("Список ключевых слов: Rust, Tokio"), "{}", prompt);
+        assert!(prompt.contains("«Новости» 1970-01-01 00:00 UTC"), "{}", prompt);
+        assert!(!prompt.contains("{keywords}"));
+    }
+}
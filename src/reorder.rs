@@ -0,0 +1,184 @@
+//! Буферы, которые выпускают посты в порядке публикации в источнике.
+
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+enum Slot<V> {
+    /// Пост ещё обрабатывается
+    Pending,
+    /// Обработка закончена, `None` — публиковать нечего
+    Done(Option<V>),
+}
+
+/// Буфер одного источника. Каждый пост резервирует место по своему id,
+/// готовые результаты выходят только когда готовы все более ранние
+pub struct ReorderBuffer<V> {
+    slots: BTreeMap<i32, Slot<V>>,
+}
+
+impl<V> Default for ReorderBuffer<V> {
+    fn default() -> Self {
+        Self {
+            slots: BTreeMap::new(),
+        }
+    }
+}
+
+impl<V> ReorderBuffer<V> {
+    /// Резервируем место под пост
+    pub fn reserve(&mut self, id: i32) {
+        self.slots.entry(id).or_insert(Slot::Pending);
+    }
+
+    /// Записываем результат обработки поста
+    pub fn complete(&mut self, id: i32, value: Option<V>) {
+        self.slots.insert(id, Slot::Done(value));
+    }
+
+    /// Забираем готовые результаты с начала буфера, до первого незаконченного поста
+    pub fn drain_ready(&mut self) -> Vec<V> {
+        let mut ready = Vec::new();
+        while let Some(entry) = self.slots.first_entry() {
+            if matches!(entry.get(), Slot::Pending) {
+                break;
+            }
+            if let Slot::Done(Some(value)) = entry.remove() {
+                ready.push(value);
+            }
+        }
+        ready
+    }
+}
+
+/// Общий буфер для всех источников: готовые посты держим `window`
+/// и выпускаем по времени оригинальной публикации
+pub struct TimestampWindow<V> {
+    window: Duration,
+    /// (дата публикации, chat_id, id) -> (когда стал готов, пост)
+    items: BTreeMap<(i64, i64, i32), (Instant, V)>,
+}
+
+impl<V> TimestampWindow<V> {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            items: BTreeMap::new(),
+        }
+    }
+
+    pub fn push(&mut self, date: i64, chat_id: i64, id: i32, value: V) {
+        self.items.insert((date, chat_id, id), (Instant::now(), value));
+    }
+
+    /// Забираем посты, у которых истекло окно ожидания, вместе со всеми,
+    /// опубликованными в источниках раньше них
+    pub fn drain_expired(&mut self) -> Vec<V> {
+        let now = Instant::now();
+        let Some(&latest) = self
+            .items
+            .iter()
+            .filter(|(_, (ready_at, _))| now.duration_since(*ready_at) >= self.window)
+            .map(|(key, _)| key)
+            .next_back()
+        else {
+            return Vec::new();
+        };
+
+        let rest = self.items.split_off(&latest);
+        let mut released = std::mem::replace(&mut self.items, rest);
+        // split_off оставляет сам ключ `latest` во второй половине
+        if let Some(item) = self.items.remove(&latest) {
+            released.insert(latest, item);
+        }
+        released.into_values().map(|(_, value)| value).collect()
+    }
+
+    /// Забираем всё, не дожидаясь окна. Нужно при остановке
+    pub fn drain_all(&mut self) -> Vec<V> {
+        std::mem::take(&mut self.items).into_values().map(|(_, value)| value).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn releases_out_of_order_posts_in_source_order() {
+        let mut buffer = ReorderBuffer::default();
+        for id in [1, 2, 3] {
+            buffer.reserve(id);
+        }
+
+        buffer.complete(3, Some("third"));
+        buffer.complete(2, Some("second"));
+        assert!(buffer.drain_ready().is_empty());
+
+        buffer.complete(1, Some("first"));
+        assert_eq!(buffer.drain_ready(), ["first", "second", "third"]);
+        assert!(buffer.drain_ready().is_empty());
+    }
+
+    #[test]
+    fn skipped_posts_fill_gaps() {
+        let mut buffer = ReorderBuffer::default();
+        for id in [10, 11, 12] {
+            buffer.reserve(id);
+        }
+
+        buffer.complete(10, Some("first"));
+        buffer.complete(12, Some("third"));
+        assert_eq!(buffer.drain_ready(), ["first"]);
+
+        // Пост 11 нерелевантен: место освобождается без результата
+        buffer.complete(11, None);
+        assert_eq!(buffer.drain_ready(), ["third"]);
+    }
+
+    #[test]
+    fn pending_post_blocks_later_ones() {
+        let mut buffer = ReorderBuffer::default();
+        buffer.reserve(1);
+        buffer.reserve(2);
+        buffer.complete(2, Some("second"));
+        assert!(buffer.drain_ready().is_empty());
+
+        // Повторное резервирование не сбрасывает готовый результат
+        buffer.reserve(2);
+        buffer.complete(1, None);
+        assert_eq!(buffer.drain_ready(), ["second"]);
+    }
+
+    #[test]
+    fn window_holds_posts_until_expiry() {
+        let mut window = TimestampWindow::new(Duration::from_secs(3600));
+        window.push(100, 1, 1, "a");
+        assert!(window.drain_expired().is_empty());
+        assert_eq!(window.drain_all(), ["a"]);
+        assert!(window.drain_all().is_empty());
+    }
+
+    #[test]
+    fn window_releases_by_publication_date_across_sources() {
+        let mut window = TimestampWindow::new(Duration::ZERO);
+        window.push(300, 2, 7, "b-late");
+        window.push(100, 1, 5, "a-early");
+        window.push(200, 2, 6, "b-middle");
+        assert_eq!(window.drain_expired(), ["a-early", "b-middle", "b-late"]);
+        assert!(window.drain_expired().is_empty());
+    }
+
+    #[test]
+    fn expired_post_releases_earlier_ones_still_waiting() {
+        let mut window = TimestampWindow::new(Duration::from_millis(50));
+        window.push(100, 1, 1, "early");
+        std::thread::sleep(Duration::from_millis(60));
+        // Более поздний пост ждёт своё окно, но не задерживает ранний
+        window.push(200, 2, 1, "late");
+        window.push(50, 2, 2, "earliest");
+        assert_eq!(window.drain_expired(), ["earliest", "early"]);
+        assert_eq!(window.drain_all(), ["late"]);
+    }
+}
@@ -0,0 +1,175 @@
+//! Маршруты публикации: из каких источников, с какими категориями и словами
+//! посты уходят в каждый target канал. Один пост может попасть в несколько target.
+
+use std::{collections::HashSet, sync::Arc};
+
+use crate::{
+    classifier::Verdict,
+    config::{OnDelete, Overflow, RepostMode, RouteConfig},
+    prompt::PromptProfile,
+};
+
+#[derive(Debug, Clone)]
+pub struct Route {
+    pub target: i64,
+    /// Пусто — все источники
+    sources: HashSet<i64>,
+    categories: Vec<String>,
+    /// Слова в нижнем регистре
+    keywords: Vec<String>,
+    stop_words: Vec<String>,
+    /// Свой профиль промпта, `None` — профиль источника или по умолчанию
+    pub prompt: Option<Arc<PromptProfile>>,
+    pub overflow: Overflow,
+    pub mode: RepostMode,
+    pub on_delete: OnDelete,
+    pub deleted_notice: String,
+}
+
+impl Route {
+    pub fn new(config: &RouteConfig, target: i64, sources: HashSet<i64>, prompt: Option<Arc<PromptProfile>>) -> Self {
+        let lowercase = |words: &[String]| -> Vec<String> { words.iter().map(|word| word.to_lowercase()).collect() };
+        Self {
+            target,
+            sources,
+            categories: lowercase(&config.categories),
+            keywords: lowercase(&config.keywords),
+            stop_words: lowercase(&config.stop_words),
+            prompt,
+            overflow: config.overflow,
+            mode: config.mode,
+            on_delete: config.on_delete,
+            deleted_notice: config.deleted_notice.clone(),
+        }
+    }
+
+    pub fn accepts_source(&self, chat_id: i64) -> bool {
+        self.sources.is_empty() || self.sources.contains(&chat_id)
+    }
+
+    /// Подходит ли пост маршруту. Слова ищем и в оригинале, и в ответе модели
+    pub fn matches(&self, chat_id: i64, verdict: &Verdict, original: &str) -> bool {
+        if !self.accepts_source(chat_id) || !self.categories.contains(&verdict.category) {
+            return false;
+        }
+
+        let text = format!("{}\n{}", original, verdict.text).to_lowercase();
+        let has_keyword = self.keywords.is_empty() || self.keywords.iter().any(|word| text.contains(word));
+        let has_stop_word = self.stop_words.iter().any(|word| text.contains(word));
+        has_keyword && !has_stop_word
+    }
+}
+
+/// Подходящие маршруты, по одному на target: первый из совпавших
+pub fn targets<'a>(
+    routes: impl IntoIterator<Item = &'a Route>,
+    chat_id: i64,
+    verdict: &Verdict,
+    original: &str,
+) -> Vec<&'a Route> {
+    let mut targets: Vec<&Route> = Vec::new();
+    for route in routes {
+        if route.matches(chat_id, verdict, original) && !targets.iter().any(|matched| matched.target == route.target) {
+            targets.push(route);
+        }
+    }
+    targets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn route(target: i64, sources: &[i64], config: RouteConfig) -> Route {
+        Route::new(&config, target, sources.iter().copied().collect(), None)
+    }
+
+    fn verdict(category: &str, text: &str) -> Verdict {
+        Verdict {
+            category: category.to_string(),
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn matches_source_and_category() {
+        let any_source = route(100, &[], RouteConfig::default());
+        let one_source = route(100, &[1], RouteConfig::default());
+        let relevant = verdict("релевантный", "Новая модель");
+
+        assert!(any_source.matches(1, &relevant, "New model"));
+        assert!(any_source.matches(2, &relevant, "New model"));
+        assert!(one_source.matches(1, &relevant, "New model"));
+        assert!(!one_source.matches(2, &relevant, "New model"));
+        assert!(!any_source.matches(1, &verdict("реклама", "Скидки"), "Sale"));
+    }
+
+    #[test]
+    fn extra_categories() {
+        let ads = route(
+            100,
+            &[],
+            RouteConfig {
+                categories: vec!["реклама".to_string()],
+                ..Default::default()
+            },
+        );
+        assert!(ads.matches(1, &verdict("реклама", "Скидки"), ""));
+        assert!(!ads.matches(1, &verdict("релевантный", "Новая модель"), ""));
+    }
+
+    #[test]
+    fn keywords_are_case_insensitive_in_original_or_rewrite() {
+        let llm = route(
+            100,
+            &[],
+            RouteConfig {
+                keywords: vec!["OpenAI".to_string(), "нейросеть".to_string()],
+                ..Default::default()
+            },
+        );
+
+        assert!(llm.matches(1, &verdict("релевантный", "Пересказ"), "openai released a model"));
+        assert!(llm.matches(1, &verdict("релевантный", "Новая НЕЙРОСЕТЬ"), "Original"));
+        assert!(!llm.matches(1, &verdict("релевантный", "Погода"), "Weather"));
+    }
+
+    #[test]
+    fn stop_words_win_over_keywords() {
+        let no_politics = route(
+            100,
+            &[],
+            RouteConfig {
+                keywords: vec!["ИИ".to_string()],
+                stop_words: vec!["Политика".to_string()],
+                ..Default::default()
+            },
+        );
+
+        assert!(no_politics.matches(1, &verdict("релевантный", "ИИ в медицине"), ""));
+        assert!(!no_politics.matches(1, &verdict("релевантный", "ИИ и политика"), ""));
+        assert!(!no_politics.matches(1, &verdict("релевантный", "ИИ"), "ПОЛИТИКА"));
+    }
+
+    #[test]
+    fn one_route_per_target() {
+        let first = route(100, &[], RouteConfig::default());
+        let duplicate = route(100, &[], RouteConfig::default());
+        let other = route(200, &[], RouteConfig::default());
+        let filtered = route(
+            300,
+            &[],
+            RouteConfig {
+                keywords: vec!["rust".to_string()],
+                ..Default::default()
+            },
+        );
+        let routes = [first, duplicate, other, filtered];
+
+        let matched: Vec<i64> = targets(&routes, 1, &verdict("релевантный", "Новая модель"), "")
+            .into_iter()
+            .map(|route| route.target)
+            .collect();
+        assert_eq!(matched, [100, 200]);
+    }
+}
@@ -0,0 +1,397 @@
+//! JSON хранилище: снапшот `history.json` + журнал `history.journal`.
+//!
+//! Каждое изменение истории дописывается в журнал одной строкой. Раз в
+//! `compact_every` записей снапшот переписывается атомарно (временный файл +
+//! rename) и журнал обнуляется. Оборванная при падении последняя строка
+//! журнала при загрузке пропускается.
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::AsyncWriteExt,
+    sync::Mutex,
+};
+
+use super::{Result, Storage};
+use crate::{
+    history::{Decision, History, Retention, TargetRef},
+    llm::BoxFuture,
+    log_info, log_warn,
+    outbox::{Outbox, OutboxEntry},
+};
+
+pub const HISTORY_FILE: &str = "history.json";
+pub const JOURNAL_FILE: &str = "history.journal";
+pub const OUTBOX_FILE: &str = "outbox.json";
+
+/// Изменение истории, одна строка журнала
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum JournalOp {
+    Processed { chat_id: i64, ids: Vec<i32> },
+    HighWater { chat_id: i64, id: i32 },
+    Mapping { chat_id: i64, id: i32, target: TargetRef },
+    Unmapped { chat_id: i64, id: i32 },
+}
+
+impl JournalOp {
+    fn apply(self, history: &mut History) {
+        match self {
+            JournalOp::Processed { chat_id, ids } => history.mark_processed(chat_id, &ids),
+            JournalOp::HighWater { chat_id, id } => {
+                history.raise_high_water(chat_id, id);
+            }
+            JournalOp::Mapping { chat_id, id, target } => history.add_mapping(chat_id, id, target),
+            JournalOp::Unmapped { chat_id, id } => history.remove_mapping(chat_id, id),
+        }
+    }
+}
+
+struct JsonState {
+    history: History,
+    outbox: Outbox,
+    journal: File,
+    /// Записей в журнале с последней компакции
+    journal_len: usize,
+}
+
+pub struct JsonStorage {
+    history_path: PathBuf,
+    journal_path: PathBuf,
+    outbox_path: PathBuf,
+    retention: Retention,
+    compact_every: usize,
+    state: Mutex<JsonState>,
+}
+
+pub async fn read_history(path: impl AsRef<Path>) -> Result<History> {
+    if tokio::fs::try_exists(&path).await? {
+        let data = tokio::fs::read_to_string(path).await?;
+        Ok(serde_json::from_str(&data)?)
+    } else {
+        Ok(History::default())
+    }
+}
+
+async fn read_outbox(path: impl AsRef<Path>) -> Result<Outbox> {
+    if tokio::fs::try_exists(&path).await? {
+        let data = tokio::fs::read_to_string(path).await?;
+        Ok(serde_json::from_str(&data)?)
+    } else {
+        Ok(Outbox::default())
+    }
+}
+
+/// Проигрываем журнал поверх снапшота, возвращаем число применённых записей
+async fn replay_journal(path: &Path, history: &mut History) -> Result<usize> {
+    if !tokio::fs::try_exists(path).await? {
+        return Ok(0);
+    }
+
+    let data = tokio::fs::read_to_string(path).await?;
+    let mut applied = 0;
+    for (number, line) in data.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<JournalOp>(line) {
+            Ok(op) => {
+                op.apply(history);
+                applied += 1;
+            }
+            Err(e) => log_warn!("Skipping broken journal line {}: {}", number + 1, e),
+        }
+    }
+    Ok(applied)
+}
+
+/// Пишем во временный файл рядом и переименовываем поверх: файл либо старый, либо новый целиком
+async fn write_atomic<V: Serialize>(path: &Path, value: &V) -> Result<()> {
+    let data = serde_json::to_vec(value)?;
+    let tmp_path = path.with_extension("tmp");
+
+    let mut tmp = File::create(&tmp_path).await?;
+    tmp.write_all(&data).await?;
+    tmp.sync_all().await?;
+    drop(tmp);
+
+    tokio::fs::rename(&tmp_path, path).await?;
+    Ok(())
+}
+
+async fn open_journal(path: &Path) -> Result<File> {
+    Ok(OpenOptions::new().create(true).append(true).open(path).await?)
+}
+
+impl JsonStorage {
+    pub async fn open(
+        history_path: impl AsRef<Path>,
+        journal_path: impl AsRef<Path>,
+        outbox_path: impl AsRef<Path>,
+        retention: Retention,
+        compact_every: usize,
+    ) -> Result<Self> {
+        let mut history = read_history(&history_path).await?;
+        let journal_len = replay_journal(journal_path.as_ref(), &mut history).await?;
+        let outbox = read_outbox(&outbox_path).await?;
+        let journal = open_journal(journal_path.as_ref()).await?;
+
+        let storage = Self {
+            history_path: history_path.as_ref().to_path_buf(),
+            journal_path: journal_path.as_ref().to_path_buf(),
+            outbox_path: outbox_path.as_ref().to_path_buf(),
+            retention,
+            compact_every: compact_every.max(1),
+            state: Mutex::new(JsonState {
+                history,
+                outbox,
+                journal,
+                journal_len,
+            }),
+        };
+
+        // Сразу сворачиваем журнал, оставшийся с прошлого запуска
+        if journal_len > 0 {
+            log_info!("Replayed {} journal records", journal_len);
+            storage.compact().await?;
+        }
+        Ok(storage)
+    }
+
+    /// Переписываем снапшот с учётом retention и обнуляем журнал
+    pub async fn compact(&self) -> Result<()> {
+        let mut state = self.state.lock().await;
+        self.compact_locked(&mut state).await
+    }
+
+    async fn compact_locked(&self, state: &mut JsonState) -> Result<()> {
+        state.history.trim(&self.retention);
+        write_atomic(&self.history_path, &state.history).await?;
+
+        // Снапшот уже на диске, журнал можно начинать заново
+        state.journal = File::create(&self.journal_path).await?;
+        state.journal.sync_all().await?;
+        state.journal = open_journal(&self.journal_path).await?;
+        state.journal_len = 0;
+        Ok(())
+    }
+
+    async fn append(&self, op: JournalOp) -> Result<()> {
+        let mut line = serde_json::to_vec(&op)?;
+        line.push(b'\n');
+
+        let mut state = self.state.lock().await;
+        state.journal.write_all(&line).await?;
+        state.journal.flush().await?;
+        op.apply(&mut state.history);
+        state.journal_len += 1;
+
+        if state.journal_len >= self.compact_every {
+            self.compact_locked(&mut state).await?;
+        }
+        Ok(())
+    }
+}
+
+impl Storage for JsonStorage {
+    fn name(&self) -> &'static str {
+        "json"
+    }
+
+    fn load_history(&self) -> BoxFuture<'_, Result<History>> {
+        Box::pin(async move { Ok(self.state.lock().await.history.clone()) })
+    }
+
+    fn load_outbox(&self) -> BoxFuture<'_, Result<Outbox>> {
+        Box::pin(async move { Ok(self.state.lock().await.outbox.clone()) })
+    }
+
+    fn mark_processed<'a>(&'a self, chat_id: i64, ids: &'a [i32]) -> BoxFuture<'a, Result<()>> {
+        Box::pin(self.append(JournalOp::Processed {
+            chat_id,
+            ids: ids.to_vec(),
+        }))
+    }
+
+    fn set_high_water(&self, chat_id: i64, id: i32) -> BoxFuture<'_, Result<()>> {
+        Box::pin(self.append(JournalOp::HighWater { chat_id, id }))
+    }
+
+    fn save_outbox<'a>(&'a self, chat_id: i64, entries: &'a [(i32, Option<OutboxEntry>)]) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let mut state = self.state.lock().await;
+            for (id, entry) in entries {
+                match entry {
+                    Some(entry) => state.outbox.put(chat_id, *id, entry.clone()),
+                    None => state.outbox.remove(chat_id, &[*id]),
+                }
+            }
+            // Outbox небольшой (только посты в работе), переписываем целиком, но атомарно
+            write_atomic(&self.outbox_path, &state.outbox).await
+        })
+    }
+
+    /// В JSON режиме решения живут только в outbox, пока пост в работе
+    fn record_decision<'a>(&'a self, _chat_id: i64, _id: i32, _decision: &'a Decision) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    fn record_mapping<'a>(&'a self, chat_id: i64, id: i32, target: &'a TargetRef) -> BoxFuture<'a, Result<()>> {
+        Box::pin(self.append(JournalOp::Mapping {
+            chat_id,
+            id,
+            target: target.clone(),
+        }))
+    }
+
+    fn remove_mapping(&self, chat_id: i64, id: i32) -> BoxFuture<'_, Result<()>> {
+        Box::pin(self.append(JournalOp::Unmapped { chat_id, id }))
+    }
+
+    /// Переносим журнал в снапшот, чтобы следующий запуск не проигрывал его заново
+    fn flush(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(self.compact())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    /// Пустой каталог для файлов теста
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("zad-json-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    async fn open(dir: &Path, retention: Retention, compact_every: usize) -> JsonStorage {
+        JsonStorage::open(
+            dir.join(HISTORY_FILE),
+            dir.join(JOURNAL_FILE),
+            dir.join(OUTBOX_FILE),
+            retention,
+            compact_every,
+        )
+        .await
+        .unwrap()
+    }
+
+    fn journal_lines(dir: &Path) -> usize {
+        std::fs::read_to_string(dir.join(JOURNAL_FILE)).unwrap().lines().count()
+    }
+
+    fn target(chat_id: i64, message_ids: &[i32]) -> TargetRef {
+        TargetRef {
+            chat_id,
+            message_ids: message_ids.to_vec(),
+            edit_date: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn journal_is_replayed_after_crash() {
+        let dir = temp_dir("replay");
+        {
+            let storage = open(&dir, Retention::default(), 100).await;
+            storage.mark_processed(1, &[5, 6]).await.unwrap();
+            storage.set_high_water(1, 6).await.unwrap();
+            storage.record_mapping(1, 6, &target(100, &[40])).await.unwrap();
+            storage.record_mapping(1, 5, &target(100, &[39])).await.unwrap();
+            storage.remove_mapping(1, 5).await.unwrap();
+            // Падение без flush: снапшота нет, всё только в журнале
+        }
+        assert!(!dir.join(HISTORY_FILE).exists());
+        assert_eq!(journal_lines(&dir), 5);
+        // Строка, оборванная на середине записи
+        let mut journal = std::fs::OpenOptions::new().append(true).open(dir.join(JOURNAL_FILE)).unwrap();
+        std::io::Write::write_all(&mut journal, b"{\"op\":\"processed\",\"chat_id\":1,\"ids\":[7").unwrap();
+        drop(journal);
+
+        let storage = open(&dir, Retention::default(), 100).await;
+        let history = storage.load_history().await.unwrap();
+        assert!(history.is_processed(1, 5));
+        assert!(history.is_processed(1, 6));
+        assert!(!history.is_processed(1, 7));
+        assert_eq!(history.high_water[&1], 6);
+        assert_eq!(history.copies(1, 6), [target(100, &[40])]);
+        assert!(history.copies(1, 5).is_empty());
+
+        // Проигранный журнал сразу свёрнут в снапшот
+        assert_eq!(journal_lines(&dir), 0);
+        let snapshot = read_history(dir.join(HISTORY_FILE)).await.unwrap();
+        assert!(snapshot.is_processed(1, 6));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn journal_is_compacted_every_n_records() {
+        let dir = temp_dir("compact");
+        let storage = open(&dir, Retention::default(), 3).await;
+
+        storage.mark_processed(1, &[1]).await.unwrap();
+        storage.mark_processed(1, &[2]).await.unwrap();
+        assert_eq!(journal_lines(&dir), 2);
+        assert!(!dir.join(HISTORY_FILE).exists());
+
+        storage.mark_processed(1, &[3]).await.unwrap();
+        assert_eq!(journal_lines(&dir), 0);
+        let snapshot = read_history(dir.join(HISTORY_FILE)).await.unwrap();
+        assert_eq!(snapshot.messages[&1].len(), 3);
+
+        storage.mark_processed(1, &[4]).await.unwrap();
+        assert_eq!(journal_lines(&dir), 1);
+        storage.flush().await.unwrap();
+        assert_eq!(journal_lines(&dir), 0);
+        assert!(read_history(dir.join(HISTORY_FILE)).await.unwrap().is_processed(1, 4));
+        assert!(!dir.join(HISTORY_FILE).with_extension("tmp").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn compaction_applies_retention() {
+        let dir = temp_dir("retention");
+        let retention = Retention {
+            default: 2,
+            per_chat: HashMap::from([(2, 1)]),
+        };
+        let storage = open(&dir, retention, 100).await;
+        storage.mark_processed(1, &[1, 2, 3]).await.unwrap();
+        storage.mark_processed(2, &[7, 8]).await.unwrap();
+        storage.record_mapping(1, 1, &target(100, &[50])).await.unwrap();
+        storage.record_mapping(1, 3, &target(100, &[51])).await.unwrap();
+        storage.flush().await.unwrap();
+
+        let snapshot = read_history(dir.join(HISTORY_FILE)).await.unwrap();
+        assert_eq!(snapshot.messages[&1].iter().copied().collect::<Vec<_>>(), [2, 3]);
+        assert_eq!(snapshot.messages[&2].iter().copied().collect::<Vec<_>>(), [8]);
+        assert!(snapshot.copies(1, 1).is_empty());
+        assert_eq!(snapshot.copies(1, 3), [target(100, &[51])]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn outbox_survives_reopen() {
+        let dir = temp_dir("outbox");
+        {
+            let storage = open(&dir, Retention::default(), 100).await;
+            let mut outbox = Outbox::default();
+            outbox.track(1, 5, None);
+            outbox.track(1, 6, None);
+            let entries = [(5, outbox.get(1, 5).cloned()), (6, outbox.get(1, 6).cloned())];
+            storage.save_outbox(1, &entries).await.unwrap();
+            storage.save_outbox(1, &[(5, None)]).await.unwrap();
+        }
+
+        let storage = open(&dir, Retention::default(), 100).await;
+        let outbox = storage.load_outbox().await.unwrap();
+        assert!(outbox.get(1, 5).is_none());
+        assert!(outbox.get(1, 6).is_some());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
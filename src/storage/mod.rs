@@ -0,0 +1,77 @@
+//! Хранилище состояния бота: обработанные id, отметки источников, outbox,
+//! решения классификатора и соответствие постов источника копиям в target.
+//!
+//! Пайплайн держит [`History`] и [`Outbox`] в памяти, а хранилище получает
+//! каждое изменение. Бэкенд выбирается секцией `storage` в `config.json`.
+
+use std::sync::Arc;
+
+use crate::{
+    config::{StorageBackend, StorageConfig},
+    history::{Decision, History, Retention, TargetRef},
+    llm::BoxFuture,
+    outbox::{Outbox, OutboxEntry},
+};
+
+pub mod json;
+pub mod postgres;
+pub mod sqlite;
+
+pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+pub trait Storage: Send + Sync {
+    /// Имя бэкенда для логов
+    fn name(&self) -> &'static str;
+
+    fn load_history(&self) -> BoxFuture<'_, Result<History>>;
+
+    fn load_outbox(&self) -> BoxFuture<'_, Result<Outbox>>;
+
+    /// Пост дошёл до финала (отправлен или не релевантный)
+    fn mark_processed<'a>(&'a self, chat_id: i64, ids: &'a [i32]) -> BoxFuture<'a, Result<()>>;
+
+    fn set_high_water(&self, chat_id: i64, id: i32) -> BoxFuture<'_, Result<()>>;
+
+    /// Записываем состояние записей outbox. `None` — запись удалена
+    fn save_outbox<'a>(&'a self, chat_id: i64, entries: &'a [(i32, Option<OutboxEntry>)]) -> BoxFuture<'a, Result<()>>;
+
+    fn record_decision<'a>(&'a self, chat_id: i64, id: i32, decision: &'a Decision) -> BoxFuture<'a, Result<()>>;
+
+    /// Пост источника `id` опубликован как `target`
+    fn record_mapping<'a>(&'a self, chat_id: i64, id: i32, target: &'a TargetRef) -> BoxFuture<'a, Result<()>>;
+
+    /// Пост источника `id` удалён, его копии больше не отслеживаем
+    fn remove_mapping(&self, chat_id: i64, id: i32) -> BoxFuture<'_, Result<()>>;
+
+    /// Дописываем всё на диск перед остановкой. После этого хранилище не используется
+    fn flush(&self) -> BoxFuture<'_, Result<()>>;
+}
+
+/// Открываем хранилище по конфигу. `retention` ограничивает историю обработанных id в любом бэкенде
+pub async fn open_storage(config: &StorageConfig, retention: Retention) -> Result<Arc<dyn Storage>> {
+    match config.backend {
+        StorageBackend::Json => {
+            let storage = json::JsonStorage::open(
+                json::HISTORY_FILE,
+                json::JOURNAL_FILE,
+                json::OUTBOX_FILE,
+                retention,
+                config.compact_every,
+            )
+            .await?;
+            Ok(Arc::new(storage))
+        }
+        StorageBackend::Postgres => {
+            let url = config.url.as_deref().ok_or("storage.url is required for postgres")?;
+            let storage = postgres::PgStorage::connect(url, retention).await?;
+            storage.import_json_history(json::HISTORY_FILE).await?;
+            Ok(Arc::new(storage))
+        }
+        StorageBackend::Sqlite => {
+            let path = config.path.as_deref().unwrap_or(sqlite::DEFAULT_PATH);
+            let storage = sqlite::SqliteStorage::open(path, retention).await?;
+            storage.import_json_history(json::HISTORY_FILE).await?;
+            Ok(Arc::new(storage))
+        }
+    }
+}
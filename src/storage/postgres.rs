@@ -0,0 +1,287 @@
+use std::path::Path;
+
+use sqlx::{
+    postgres::{PgPool, PgPoolOptions},
+    types::Json,
+    PgConnection, Row,
+};
+
+use super::{json::read_history, Result, Storage};
+use crate::{
+    history::{Decision, History, Retention, TargetRef},
+    llm::BoxFuture,
+    log_info,
+    outbox::{Outbox, OutboxEntry},
+};
+
+/// Ключ в `storage_meta`, что history.json уже перенесён
+const JSON_IMPORT_KEY: &str = "json_history_imported";
+
+pub struct PgStorage {
+    pool: PgPool,
+    retention: Retention,
+}
+
+impl PgStorage {
+    /// Подключаемся и применяем встроенные миграции.
+    /// `retention` ограничивает число обработанных id на источник
+    pub async fn connect(url: &str, retention: Retention) -> Result<Self> {
+        let pool = PgPoolOptions::new().max_connections(5).connect(url).await?;
+        sqlx::migrate!("./migrations/postgres").run(&pool).await?;
+        Ok(Self { pool, retention })
+    }
+
+    /// Однократно переносим существующий `history.json` в базу
+    pub async fn import_json_history(&self, path: impl AsRef<Path>) -> Result<()> {
+        let imported = sqlx::query("SELECT 1 FROM storage_meta WHERE key = $1")
+            .bind(JSON_IMPORT_KEY)
+            .fetch_optional(&self.pool)
+            .await?
+            .is_some();
+        if imported || !tokio::fs::try_exists(&path).await? {
+            return Ok(());
+        }
+
+        let history = read_history(&path).await?;
+        let mut tx = self.pool.begin().await?;
+        for (chat_id, ids) in &history.messages {
+            sqlx::query(
+                "INSERT INTO processed_messages (chat_id, message_id)
+                 SELECT $1, unnest($2::INTEGER[]) ON CONFLICT DO NOTHING",
+            )
+            .bind(chat_id)
+            .bind(ids.iter().copied().collect::<Vec<i32>>())
+            .execute(&mut *tx)
+            .await?;
+        }
+        for (chat_id, id) in &history.high_water {
+            sqlx::query(
+                "INSERT INTO high_water (chat_id, message_id) VALUES ($1, $2)
+                 ON CONFLICT (chat_id) DO UPDATE SET message_id = GREATEST(high_water.message_id, EXCLUDED.message_id)",
+            )
+            .bind(chat_id)
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        }
+        for (chat_id, messages) in &history.mapping {
+            for (id, targets) in messages {
+                for target in targets {
+                    insert_mapping(&mut *tx, *chat_id, *id, target).await?;
+                }
+            }
+        }
+        sqlx::query("INSERT INTO storage_meta (key, value) VALUES ($1, now()::TEXT)")
+            .bind(JSON_IMPORT_KEY)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        log_info!(
+            "Imported {} chats from {} into postgres",
+            history.messages.len(),
+            path.as_ref().display()
+        );
+        Ok(())
+    }
+}
+
+async fn insert_mapping<'e, E>(executor: E, chat_id: i64, id: i32, target: &TargetRef) -> Result<()>
+where
+    E: sqlx::PgExecutor<'e>,
+{
+    sqlx::query(
+        "INSERT INTO message_mapping (source_chat_id, source_message_id, target_chat_id, target_message_ids, source_edit_date)
+         VALUES ($1, $2, $3, $4, $5)
+         ON CONFLICT (source_chat_id, source_message_id, target_chat_id)
+         DO UPDATE SET target_message_ids = EXCLUDED.target_message_ids, source_edit_date = EXCLUDED.source_edit_date",
+    )
+    .bind(chat_id)
+    .bind(id)
+    .bind(target.chat_id)
+    .bind(&target.message_ids)
+    .bind(target.edit_date)
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+/// Оставляем `limit` последних обработанных id источника и копии не старше самого раннего из них,
+/// как [`History::trim`]
+async fn trim_processed(conn: &mut PgConnection, chat_id: i64, limit: usize) -> Result<()> {
+    sqlx::query(
+        "DELETE FROM processed_messages WHERE chat_id = $1 AND message_id <= (
+             SELECT message_id FROM processed_messages WHERE chat_id = $1 ORDER BY message_id DESC LIMIT 1 OFFSET $2
+         )",
+    )
+    .bind(chat_id)
+    .bind(limit as i64)
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query(
+        "DELETE FROM message_mapping WHERE source_chat_id = $1 AND source_message_id < (
+             SELECT MIN(message_id) FROM processed_messages WHERE chat_id = $1
+         )",
+    )
+    .bind(chat_id)
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+impl Storage for PgStorage {
+    fn name(&self) -> &'static str {
+        "postgres"
+    }
+
+    fn load_history(&self) -> BoxFuture<'_, Result<History>> {
+        Box::pin(async move {
+            let mut history = History::default();
+
+            let rows = sqlx::query("SELECT chat_id, message_id FROM processed_messages ORDER BY chat_id, message_id")
+                .fetch_all(&self.pool)
+                .await?;
+            for row in rows {
+                history.mark_processed(row.try_get("chat_id")?, &[row.try_get("message_id")?]);
+            }
+
+            let rows = sqlx::query("SELECT chat_id, message_id FROM high_water")
+                .fetch_all(&self.pool)
+                .await?;
+            for row in rows {
+                history.high_water.insert(row.try_get("chat_id")?, row.try_get("message_id")?);
+            }
+
+            let rows = sqlx::query(
+                "SELECT source_chat_id, source_message_id, target_chat_id, target_message_ids, source_edit_date
+                 FROM message_mapping",
+            )
+            .fetch_all(&self.pool)
+            .await?;
+            for row in rows {
+                let target = TargetRef {
+                    chat_id: row.try_get("target_chat_id")?,
+                    message_ids: row.try_get("target_message_ids")?,
+                    edit_date: row.try_get("source_edit_date")?,
+                };
+                history.add_mapping(row.try_get("source_chat_id")?, row.try_get("source_message_id")?, target);
+            }
+
+            Ok(history)
+        })
+    }
+
+    fn load_outbox(&self) -> BoxFuture<'_, Result<Outbox>> {
+        Box::pin(async move {
+            let mut outbox = Outbox::default();
+            let rows = sqlx::query("SELECT chat_id, message_id, entry FROM outbox")
+                .fetch_all(&self.pool)
+                .await?;
+            for row in rows {
+                let Json(entry): Json<OutboxEntry> = row.try_get("entry")?;
+                outbox.put(row.try_get("chat_id")?, row.try_get("message_id")?, entry);
+            }
+            Ok(outbox)
+        })
+    }
+
+    fn mark_processed<'a>(&'a self, chat_id: i64, ids: &'a [i32]) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let mut tx = self.pool.begin().await?;
+            sqlx::query(
+                "INSERT INTO processed_messages (chat_id, message_id)
+                 SELECT $1, unnest($2::INTEGER[]) ON CONFLICT DO NOTHING",
+            )
+            .bind(chat_id)
+            .bind(ids)
+            .execute(&mut *tx)
+            .await?;
+            trim_processed(&mut tx, chat_id, self.retention.limit(chat_id)).await?;
+            tx.commit().await?;
+            Ok(())
+        })
+    }
+
+    fn set_high_water(&self, chat_id: i64, id: i32) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            sqlx::query(
+                "INSERT INTO high_water (chat_id, message_id) VALUES ($1, $2)
+                 ON CONFLICT (chat_id) DO UPDATE SET message_id = GREATEST(high_water.message_id, EXCLUDED.message_id)",
+            )
+            .bind(chat_id)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+            Ok(())
+        })
+    }
+
+    fn save_outbox<'a>(&'a self, chat_id: i64, entries: &'a [(i32, Option<OutboxEntry>)]) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let mut tx = self.pool.begin().await?;
+            for (id, entry) in entries {
+                match entry {
+                    Some(entry) => {
+                        sqlx::query(
+                            "INSERT INTO outbox (chat_id, message_id, entry) VALUES ($1, $2, $3)
+                             ON CONFLICT (chat_id, message_id) DO UPDATE SET entry = EXCLUDED.entry, updated_at = now()",
+                        )
+                        .bind(chat_id)
+                        .bind(id)
+                        .bind(Json(entry))
+                        .execute(&mut *tx)
+                        .await?;
+                    }
+                    None => {
+                        sqlx::query("DELETE FROM outbox WHERE chat_id = $1 AND message_id = $2")
+                            .bind(chat_id)
+                            .bind(id)
+                            .execute(&mut *tx)
+                            .await?;
+                    }
+                }
+            }
+            tx.commit().await?;
+            Ok(())
+        })
+    }
+
+    fn record_decision<'a>(&'a self, chat_id: i64, id: i32, decision: &'a Decision) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            sqlx::query(
+                "INSERT INTO decisions (chat_id, message_id, relevant, category, text, model) VALUES ($1, $2, $3, $4, $5, $6)",
+            )
+            .bind(chat_id)
+            .bind(id)
+            .bind(decision.is_relevant())
+            .bind(&decision.category)
+            .bind(&decision.text)
+            .bind(&decision.model)
+            .execute(&self.pool)
+            .await?;
+            Ok(())
+        })
+    }
+
+    fn record_mapping<'a>(&'a self, chat_id: i64, id: i32, target: &'a TargetRef) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move { insert_mapping(&self.pool, chat_id, id, target).await })
+    }
+
+    fn remove_mapping(&self, chat_id: i64, id: i32) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            sqlx::query("DELETE FROM message_mapping WHERE source_chat_id = $1 AND source_message_id = $2")
+                .bind(chat_id)
+                .bind(id)
+                .execute(&self.pool)
+                .await?;
+            Ok(())
+        })
+    }
+
+    fn flush(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            self.pool.close().await;
+            Ok(())
+        })
+    }
+}
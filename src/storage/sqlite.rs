@@ -0,0 +1,415 @@
+use std::{path::Path, str::FromStr};
+
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePool, SqlitePoolOptions, SqliteSynchronous},
+    types::Json,
+    Row, SqliteConnection, SqliteExecutor,
+};
+
+use super::{json::read_history, Result, Storage};
+use crate::{
+    history::{Decision, History, Retention, TargetRef},
+    llm::BoxFuture,
+    log_info,
+    outbox::{Outbox, OutboxEntry},
+};
+
+pub const DEFAULT_PATH: &str = "replier.db";
+
+/// Ключ в `storage_meta`, что history.json уже перенесён
+const JSON_IMPORT_KEY: &str = "json_history_imported";
+
+/// Хранилище в одном файле SQLite, для одиночных установок без Postgres
+pub struct SqliteStorage {
+    pool: SqlitePool,
+    retention: Retention,
+}
+
+impl SqliteStorage {
+    /// Открываем (или создаём) базу в WAL режиме и применяем встроенные миграции.
+    /// `retention` ограничивает число обработанных id на источник
+    pub async fn open(path: &str, retention: Retention) -> Result<Self> {
+        let options = SqliteConnectOptions::from_str(&format!("sqlite://{}", path))?
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal)
+            // В WAL режиме NORMAL не теряет целостность при падении процесса
+            .synchronous(SqliteSynchronous::Normal);
+        Self::connect(options, 4, retention).await
+    }
+
+    /// База в памяти: живёт, пока открыто её единственное соединение
+    #[cfg(test)]
+    async fn in_memory(retention: Retention) -> Result<Self> {
+        Self::connect(SqliteConnectOptions::from_str("sqlite::memory:")?, 1, retention).await
+    }
+
+    async fn connect(options: SqliteConnectOptions, max_connections: u32, retention: Retention) -> Result<Self> {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(max_connections)
+            .connect_with(options)
+            .await?;
+        sqlx::migrate!("./migrations/sqlite").run(&pool).await?;
+        Ok(Self { pool, retention })
+    }
+
+    /// Однократно переносим существующий `history.json` в базу
+    pub async fn import_json_history(&self, path: impl AsRef<Path>) -> Result<()> {
+        let imported = sqlx::query("SELECT 1 FROM storage_meta WHERE key = ?")
+            .bind(JSON_IMPORT_KEY)
+            .fetch_optional(&self.pool)
+            .await?
+            .is_some();
+        if imported || !tokio::fs::try_exists(&path).await? {
+            return Ok(());
+        }
+
+        let history = read_history(&path).await?;
+        let mut tx = self.pool.begin().await?;
+        for (chat_id, ids) in &history.messages {
+            for id in ids {
+                insert_processed(&mut *tx, *chat_id, *id).await?;
+            }
+        }
+        for (chat_id, id) in &history.high_water {
+            upsert_high_water(&mut *tx, *chat_id, *id).await?;
+        }
+        for (chat_id, messages) in &history.mapping {
+            for (id, targets) in messages {
+                for target in targets {
+                    insert_mapping(&mut *tx, *chat_id, *id, target).await?;
+                }
+            }
+        }
+        sqlx::query("INSERT INTO storage_meta (key, value) VALUES (?, CURRENT_TIMESTAMP)")
+            .bind(JSON_IMPORT_KEY)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        log_info!(
+            "Imported {} chats from {} into sqlite",
+            history.messages.len(),
+            path.as_ref().display()
+        );
+        Ok(())
+    }
+}
+
+async fn insert_processed<'e, E: SqliteExecutor<'e>>(executor: E, chat_id: i64, id: i32) -> Result<()> {
+    sqlx::query("INSERT OR IGNORE INTO processed_messages (chat_id, message_id) VALUES (?, ?)")
+        .bind(chat_id)
+        .bind(id)
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+/// Оставляем `limit` последних обработанных id источника и копии не старше самого раннего из них,
+/// как [`History::trim`]
+async fn trim_processed(conn: &mut SqliteConnection, chat_id: i64, limit: usize) -> Result<()> {
+    sqlx::query(
+        "DELETE FROM processed_messages WHERE chat_id = ? AND message_id <= (
+             SELECT message_id FROM processed_messages WHERE chat_id = ? ORDER BY message_id DESC LIMIT 1 OFFSET ?
+         )",
+    )
+    .bind(chat_id)
+    .bind(chat_id)
+    .bind(limit as i64)
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query(
+        "DELETE FROM message_mapping WHERE source_chat_id = ? AND source_message_id < (
+             SELECT MIN(message_id) FROM processed_messages WHERE chat_id = ?
+         )",
+    )
+    .bind(chat_id)
+    .bind(chat_id)
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+async fn upsert_high_water<'e, E: SqliteExecutor<'e>>(executor: E, chat_id: i64, id: i32) -> Result<()> {
+    sqlx::query(
+        "INSERT INTO high_water (chat_id, message_id) VALUES (?, ?)
+         ON CONFLICT (chat_id) DO UPDATE SET message_id = MAX(message_id, excluded.message_id)",
+    )
+    .bind(chat_id)
+    .bind(id)
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+async fn insert_mapping<'e, E: SqliteExecutor<'e>>(executor: E, chat_id: i64, id: i32, target: &TargetRef) -> Result<()> {
+    sqlx::query(
+        "INSERT INTO message_mapping (source_chat_id, source_message_id, target_chat_id, target_message_ids, source_edit_date)
+         VALUES (?, ?, ?, ?, ?)
+         ON CONFLICT (source_chat_id, source_message_id, target_chat_id)
+         DO UPDATE SET target_message_ids = excluded.target_message_ids, source_edit_date = excluded.source_edit_date",
+    )
+    .bind(chat_id)
+    .bind(id)
+    .bind(target.chat_id)
+    .bind(Json(&target.message_ids))
+    .bind(target.edit_date)
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+impl Storage for SqliteStorage {
+    fn name(&self) -> &'static str {
+        "sqlite"
+    }
+
+    fn load_history(&self) -> BoxFuture<'_, Result<History>> {
+        Box::pin(async move {
+            let mut history = History::default();
+
+            let rows = sqlx::query("SELECT chat_id, message_id FROM processed_messages ORDER BY chat_id, message_id")
+                .fetch_all(&self.pool)
+                .await?;
+            for row in rows {
+                history.mark_processed(row.try_get("chat_id")?, &[row.try_get("message_id")?]);
+            }
+
+            let rows = sqlx::query("SELECT chat_id, message_id FROM high_water")
+                .fetch_all(&self.pool)
+                .await?;
+            for row in rows {
+                history.high_water.insert(row.try_get("chat_id")?, row.try_get("message_id")?);
+            }
+
+            let rows = sqlx::query(
+                "SELECT source_chat_id, source_message_id, target_chat_id, target_message_ids, source_edit_date
+                 FROM message_mapping",
+            )
+            .fetch_all(&self.pool)
+            .await?;
+            for row in rows {
+                let Json(message_ids): Json<Vec<i32>> = row.try_get("target_message_ids")?;
+                let target = TargetRef {
+                    chat_id: row.try_get("target_chat_id")?,
+                    message_ids,
+                    edit_date: row.try_get("source_edit_date")?,
+                };
+                history.add_mapping(row.try_get("source_chat_id")?, row.try_get("source_message_id")?, target);
+            }
+
+            Ok(history)
+        })
+    }
+
+    fn load_outbox(&self) -> BoxFuture<'_, Result<Outbox>> {
+        Box::pin(async move {
+            let mut outbox = Outbox::default();
+            let rows = sqlx::query("SELECT chat_id, message_id, entry FROM outbox")
+                .fetch_all(&self.pool)
+                .await?;
+            for row in rows {
+                let Json(entry): Json<OutboxEntry> = row.try_get("entry")?;
+                outbox.put(row.try_get("chat_id")?, row.try_get("message_id")?, entry);
+            }
+            Ok(outbox)
+        })
+    }
+
+    fn mark_processed<'a>(&'a self, chat_id: i64, ids: &'a [i32]) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let mut tx = self.pool.begin().await?;
+            for &id in ids {
+                insert_processed(&mut *tx, chat_id, id).await?;
+            }
+            trim_processed(&mut tx, chat_id, self.retention.limit(chat_id)).await?;
+            tx.commit().await?;
+            Ok(())
+        })
+    }
+
+    fn set_high_water(&self, chat_id: i64, id: i32) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move { upsert_high_water(&self.pool, chat_id, id).await })
+    }
+
+    fn save_outbox<'a>(&'a self, chat_id: i64, entries: &'a [(i32, Option<OutboxEntry>)]) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let mut tx = self.pool.begin().await?;
+            for (id, entry) in entries {
+                match entry {
+                    Some(entry) => {
+                        sqlx::query(
+                            "INSERT INTO outbox (chat_id, message_id, entry) VALUES (?, ?, ?)
+                             ON CONFLICT (chat_id, message_id)
+                             DO UPDATE SET entry = excluded.entry, updated_at = CURRENT_TIMESTAMP",
+                        )
+                        .bind(chat_id)
+                        .bind(id)
+                        .bind(Json(entry))
+                        .execute(&mut *tx)
+                        .await?;
+                    }
+                    None => {
+                        sqlx::query("DELETE FROM outbox WHERE chat_id = ? AND message_id = ?")
+                            .bind(chat_id)
+                            .bind(id)
+                            .execute(&mut *tx)
+                            .await?;
+                    }
+                }
+            }
+            tx.commit().await?;
+            Ok(())
+        })
+    }
+
+    fn record_decision<'a>(&'a self, chat_id: i64, id: i32, decision: &'a Decision) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            sqlx::query(
+                "INSERT INTO decisions (chat_id, message_id, relevant, category, text, model) VALUES (?, ?, ?, ?, ?, ?)",
+            )
+            .bind(chat_id)
+            .bind(id)
+            .bind(decision.is_relevant())
+            .bind(&decision.category)
+            .bind(&decision.text)
+            .bind(&decision.model)
+            .execute(&self.pool)
+            .await?;
+            Ok(())
+        })
+    }
+
+    fn record_mapping<'a>(&'a self, chat_id: i64, id: i32, target: &'a TargetRef) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move { insert_mapping(&self.pool, chat_id, id, target).await })
+    }
+
+    fn remove_mapping(&self, chat_id: i64, id: i32) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            sqlx::query("DELETE FROM message_mapping WHERE source_chat_id = ? AND source_message_id = ?")
+                .bind(chat_id)
+                .bind(id)
+                .execute(&self.pool)
+                .await?;
+            Ok(())
+        })
+    }
+
+    /// Закрытие последнего соединения переносит WAL в основной файл
+    fn flush(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            self.pool.close().await;
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeSet, HashMap};
+
+    use super::*;
+    use crate::outbox::OutboxState;
+
+    const CHAT: i64 = 1;
+    const BUSY_CHAT: i64 = 2;
+
+    fn copy(message_ids: &[i32]) -> TargetRef {
+        TargetRef {
+            chat_id: 100,
+            message_ids: message_ids.to_vec(),
+            edit_date: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn retention_trims_processed_ids_and_old_copies() {
+        let retention = Retention {
+            default: 3,
+            per_chat: HashMap::from([(BUSY_CHAT, 10)]),
+        };
+        let storage = SqliteStorage::in_memory(retention).await.unwrap();
+        for chat_id in [CHAT, BUSY_CHAT] {
+            storage.record_mapping(chat_id, 1, &copy(&[11])).await.unwrap();
+            storage.record_mapping(chat_id, 4, &copy(&[14])).await.unwrap();
+            storage.mark_processed(chat_id, &[1, 2, 3]).await.unwrap();
+            storage.mark_processed(chat_id, &[4, 5]).await.unwrap();
+        }
+
+        let history = storage.load_history().await.unwrap();
+        assert_eq!(history.messages[&CHAT], BTreeSet::from([3, 4, 5]));
+        assert_eq!(history.messages[&BUSY_CHAT], BTreeSet::from([1, 2, 3, 4, 5]));
+        assert!(history.copies(CHAT, 1).is_empty());
+        assert_eq!(history.copies(CHAT, 4), [copy(&[14])]);
+        assert_eq!(history.copies(BUSY_CHAT, 1), [copy(&[11])]);
+    }
+
+    fn entry(state: OutboxState, attempts: u32) -> OutboxEntry {
+        OutboxEntry {
+            state,
+            grouped_id: Some(7),
+            attempts,
+            last_error: None,
+            delivered: vec![100],
+        }
+    }
+
+    #[tokio::test]
+    async fn round_trip() {
+        let storage = SqliteStorage::in_memory(Retention::default()).await.unwrap();
+
+        storage.mark_processed(CHAT, &[1, 2]).await.unwrap();
+        storage.mark_processed(CHAT, &[2, 3]).await.unwrap();
+        storage.set_high_water(CHAT, 3).await.unwrap();
+        storage.set_high_water(CHAT, 2).await.unwrap();
+        let edited = TargetRef {
+            edit_date: Some(1_700_000_000),
+            ..copy(&[12, 13])
+        };
+        storage.record_mapping(CHAT, 1, &copy(&[11])).await.unwrap();
+        storage.record_mapping(CHAT, 2, &copy(&[12])).await.unwrap();
+        storage.record_mapping(CHAT, 2, &edited).await.unwrap();
+        storage.record_mapping(CHAT, 3, &copy(&[14])).await.unwrap();
+        storage.remove_mapping(CHAT, 3).await.unwrap();
+
+        let history = storage.load_history().await.unwrap();
+        assert_eq!(history.messages[&CHAT], BTreeSet::from([1, 2, 3]));
+        // Отметка источника только растёт
+        assert_eq!(history.high_water[&CHAT], 3);
+        assert_eq!(history.copies(CHAT, 1), [copy(&[11])]);
+        assert_eq!(history.copies(CHAT, 2), [edited]);
+        assert!(history.copies(CHAT, 3).is_empty());
+
+        storage.save_outbox(CHAT, &[(4, Some(entry(OutboxState::Pending, 0)))]).await.unwrap();
+        storage.save_outbox(CHAT, &[(5, Some(entry(OutboxState::Sent, 0)))]).await.unwrap();
+        let failed = [(4, Some(entry(OutboxState::Failed, 5))), (5, None)];
+        storage.save_outbox(CHAT, &failed).await.unwrap();
+
+        let outbox = storage.load_outbox().await.unwrap();
+        let entry = outbox.get(CHAT, 4).unwrap();
+        assert_eq!((&entry.state, entry.attempts), (&OutboxState::Failed, 5));
+        assert_eq!((entry.grouped_id, &entry.delivered), (Some(7), &vec![100]));
+        assert!(outbox.get(CHAT, 5).is_none());
+    }
+
+    #[tokio::test]
+    async fn decisions_keep_category() {
+        let storage = SqliteStorage::in_memory(Retention::default()).await.unwrap();
+        let decision = Decision {
+            category: Some("реклама".to_string()),
+            text: None,
+            model: "mistral".to_string(),
+        };
+        storage.record_decision(CHAT, 1, &decision).await.unwrap();
+
+        let row = sqlx::query("SELECT relevant, category, text, model FROM decisions WHERE chat_id = ? AND message_id = ?")
+            .bind(CHAT)
+            .bind(1)
+            .fetch_one(&storage.pool)
+            .await
+            .unwrap();
+        assert!(!row.get::<bool, _>("relevant"));
+        assert_eq!(row.get::<Option<String>, _>("category").as_deref(), Some("реклама"));
+        assert_eq!(row.get::<Option<String>, _>("text"), None);
+        assert_eq!(row.get::<String, _>("model"), "mistral");
+    }
+}
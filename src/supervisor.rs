@@ -0,0 +1,248 @@
+//! Супервизор обработчиков источников.
+//!
+//! Каждый обработчик работает в своей задаче. Если он вернул ошибку или
+//! запаниковал, супервизор перезапускает его с нарастающей задержкой и
+//! запоминает последнюю ошибку. После `max_restarts` неудач подряд обработчик
+//! считается остановленным; когда остановлены все, `all_down` завершается.
+
+use std::{
+    any::Any,
+    collections::BTreeMap,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::{
+    sync::Notify,
+    time::{sleep, Instant},
+};
+
+use crate::{config::SupervisorConfig, llm::Result, log_error, log_info, log_warn};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorkerState {
+    Running,
+    /// Упал, ждёт перезапуска
+    Restarting,
+    /// Перезапуски исчерпаны
+    Down,
+    /// Завершился сам, без ошибки
+    Stopped,
+}
+
+#[derive(Debug, Clone)]
+pub struct WorkerHealth {
+    pub state: WorkerState,
+    /// Сколько раз обработчик перезапускался за всё время
+    pub restarts: u32,
+    pub last_error: Option<String>,
+}
+
+pub struct Supervisor {
+    config: SupervisorConfig,
+    workers: Mutex<BTreeMap<String, WorkerHealth>>,
+    changed: Notify,
+}
+
+impl Supervisor {
+    pub fn new(config: SupervisorConfig) -> Arc<Self> {
+        Arc::new(Self {
+            config,
+            workers: Mutex::new(BTreeMap::new()),
+            changed: Notify::new(),
+        })
+    }
+
+    /// Запускаем обработчик под присмотром. `worker` вызывается заново на каждый перезапуск
+    pub fn spawn<F, Fut>(self: &Arc<Self>, name: String, worker: F)
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.workers.lock().unwrap().insert(
+            name.clone(),
+            WorkerHealth {
+                state: WorkerState::Running,
+                restarts: 0,
+                last_error: None,
+            },
+        );
+
+        let this = Arc::clone(self);
+        tokio::spawn(async move {
+            let healthy_after = Duration::from_secs(this.config.healthy_after_secs);
+            let mut failures = 0;
+            loop {
+                let started = Instant::now();
+                let error = match tokio::spawn(worker()).await {
+                    Ok(Ok(())) => {
+                        log_info!("Worker {} stopped", name);
+                        this.update(&name, WorkerState::Stopped, None);
+                        return;
+                    }
+                    Ok(Err(e)) => e.to_string(),
+                    Err(e) if e.is_panic() => format!("panicked: {}", panic_message(e.into_panic())),
+                    Err(e) => e.to_string(),
+                };
+
+                if started.elapsed() >= healthy_after {
+                    failures = 0;
+                }
+                failures += 1;
+
+                if failures > this.config.max_restarts {
+                    log_error!("Worker {} is down after {} failures: {}", name, failures, error);
+                    this.update(&name, WorkerState::Down, Some(error));
+                    return;
+                }
+
+                let delay = this.config.backoff(failures);
+                log_warn!("Worker {} failed: {}, restarting in {:?}", name, error, delay);
+                this.update(&name, WorkerState::Restarting, Some(error));
+                sleep(delay).await;
+                this.update(&name, WorkerState::Running, None);
+            }
+        });
+    }
+
+    /// Снимок состояния всех обработчиков
+    pub fn health(&self) -> Vec<(String, WorkerHealth)> {
+        let workers = self.workers.lock().unwrap();
+        workers.iter().map(|(name, health)| (name.clone(), health.clone())).collect()
+    }
+
+    /// Ждём, пока не останется ни одного живого обработчика
+    pub async fn all_down(&self) {
+        loop {
+            {
+                let workers = self.workers.lock().unwrap();
+                if !workers.is_empty() && workers.values().all(|health| health.state == WorkerState::Down) {
+                    return;
+                }
+            }
+            self.changed.notified().await;
+        }
+    }
+
+    fn update(&self, name: &str, state: WorkerState, error: Option<String>) {
+        let mut workers = self.workers.lock().unwrap();
+        if let Some(health) = workers.get_mut(name) {
+            if state == WorkerState::Restarting {
+                health.restarts += 1;
+            }
+            health.state = state;
+            if error.is_some() {
+                health.last_error = error;
+            }
+        }
+        drop(workers);
+        self.changed.notify_one();
+    }
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use tokio::time::timeout;
+
+    use super::*;
+
+    fn config(max_restarts: u32) -> SupervisorConfig {
+        SupervisorConfig {
+            max_restarts,
+            backoff_base_secs: 1,
+            backoff_max_secs: 4,
+            healthy_after_secs: 10,
+        }
+    }
+
+    /// Обработчик, который проработал `secs` секунд и упал
+    async fn fail_after(secs: u64) -> Result<()> {
+        sleep(Duration::from_secs(secs)).await;
+        Err("connection lost".into())
+    }
+
+    async fn panic_after(secs: u64) -> Result<()> {
+        sleep(Duration::from_secs(secs)).await;
+        panic!("boom");
+    }
+
+    fn health(supervisor: &Supervisor, name: &str) -> WorkerHealth {
+        let workers = supervisor.health();
+        workers.into_iter().find(|(worker, _)| worker == name).unwrap().1
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn restarts_with_growing_backoff() {
+        let supervisor = Supervisor::new(config(4));
+        let starts = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&starts);
+        supervisor.spawn("source".to_string(), move || {
+            recorded.lock().unwrap().push(Instant::now());
+            fail_after(0)
+        });
+        supervisor.all_down().await;
+
+        let starts = starts.lock().unwrap();
+        let gaps: Vec<_> = starts.windows(2).map(|pair| (pair[1] - pair[0]).as_secs()).collect();
+        assert_eq!(gaps, [1, 2, 4, 4]);
+        let health = health(&supervisor, "source");
+        assert_eq!((health.state, health.restarts), (WorkerState::Down, 4));
+        assert_eq!(health.last_error.as_deref(), Some("connection lost"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn long_run_resets_failure_count() {
+        let supervisor = Supervisor::new(config(1));
+        let runs = Arc::new(AtomicU32::new(0));
+        let counter = Arc::clone(&runs);
+        supervisor.spawn("source".to_string(), move || {
+            let run = counter.fetch_add(1, Ordering::SeqCst);
+            async move {
+                if run == 3 {
+                    return Ok(());
+                }
+                // Проработал дольше healthy_after_secs, поэтому счёт неудач начинается заново
+                fail_after(10).await
+            }
+        });
+
+        let stopped = async {
+            while health(&supervisor, "source").state != WorkerState::Stopped {
+                sleep(Duration::from_secs(1)).await;
+            }
+        };
+        timeout(Duration::from_secs(3600), stopped).await.unwrap();
+        assert_eq!(health(&supervisor, "source").restarts, 3);
+        assert_eq!(runs.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn all_down_waits_for_every_worker() {
+        let supervisor = Supervisor::new(config(0));
+        supervisor.spawn("first".to_string(), || fail_after(0));
+        supervisor.spawn("second".to_string(), || panic_after(60));
+
+        // Пока второй жив, all_down не завершается
+        assert!(timeout(Duration::from_secs(30), supervisor.all_down()).await.is_err());
+        assert_eq!(health(&supervisor, "first").state, WorkerState::Down);
+        assert_eq!(health(&supervisor, "second").state, WorkerState::Running);
+
+        supervisor.all_down().await;
+        let second = health(&supervisor, "second");
+        assert_eq!(second.state, WorkerState::Down);
+        assert_eq!(second.last_error.as_deref(), Some("panicked: boom"));
+    }
+}
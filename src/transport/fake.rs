@@ -0,0 +1,279 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+};
+
+use tokio::sync::Notify;
+
+use super::{
+    split::{self, PlainText},
+    AlbumItem, ChatTransport, ChatUpdate, MediaFile, ResolvedChat, Result, SourcePost, TransportError,
+};
+
+/// Что было отправлено через [`FakeTransport`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SentPost {
+    Text {
+        chat_id: i64,
+        text: String,
+        reply_to: Option<i32>,
+        link_preview: bool,
+    },
+    Media {
+        chat_id: i64,
+        media: String,
+        caption: String,
+    },
+    /// Альбом: пары (подпись, метка медиа)
+    Album {
+        chat_id: i64,
+        items: Vec<(String, String)>,
+    },
+    Forward {
+        chat_id: i64,
+        from_chat_id: i64,
+        ids: Vec<i32>,
+    },
+    EditText {
+        chat_id: i64,
+        id: i32,
+        text: String,
+        link_preview: bool,
+    },
+    EditMedia {
+        chat_id: i64,
+        id: i32,
+        media: String,
+        caption: String,
+    },
+    Delete {
+        chat_id: i64,
+        ids: Vec<i32>,
+    },
+}
+
+#[derive(Default)]
+struct FakeState {
+    chats: HashMap<String, ResolvedChat>,
+    /// chat_id -> посты в порядке публикации
+    posts: HashMap<i64, Vec<SourcePost<String>>>,
+    /// Очередь обновлений для `next_update`
+    updates: VecDeque<ChatUpdate<String>>,
+    /// Метка медиа -> картинка для `download_image`
+    images: HashMap<String, MediaFile>,
+    sent: Vec<SentPost>,
+    /// Ошибки, которые вернут следующие отправки
+    send_errors: Vec<TransportError>,
+    next_sent_id: i32,
+}
+
+/// Транспорт в памяти: посты источников задаются скриптом, отправленное записывается.
+/// Медиа представлено строковой меткой.
+#[derive(Default)]
+pub struct FakeTransport {
+    state: Mutex<FakeState>,
+    /// Будит `next_update`, когда в очереди появилось обновление
+    updates_ready: Notify,
+}
+
+impl FakeTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Регистрируем чат, который можно будет найти по username
+    pub fn add_chat(&self, username: &str, id: i64, name: &str) {
+        self.state.lock().unwrap().chats.insert(
+            username.to_string(),
+            ResolvedChat {
+                id,
+                name: name.to_string(),
+            },
+        );
+    }
+
+    /// Публикуем пост в канале-источнике: он попадает в историю канала и в очередь обновлений
+    pub fn push_post(&self, post: SourcePost<String>) {
+        let mut state = self.state.lock().unwrap();
+        state.posts.entry(post.chat_id).or_default().push(post.clone());
+        state.updates.push_back(ChatUpdate::NewPost(post));
+        drop(state);
+
+        self.updates_ready.notify_one();
+    }
+
+    /// Редактируем пост источника: он меняется в истории канала и попадает в очередь обновлений
+    pub fn edit_post(&self, post: SourcePost<String>) {
+        let mut state = self.state.lock().unwrap();
+        let posts = state.posts.entry(post.chat_id).or_default();
+        match posts.iter_mut().find(|existing| existing.id == post.id) {
+            Some(existing) => *existing = post.clone(),
+            None => posts.push(post.clone()),
+        }
+        state.updates.push_back(ChatUpdate::EditedPost(post));
+        drop(state);
+
+        self.updates_ready.notify_one();
+    }
+
+    /// Удаляем посты источника: они пропадают из истории канала, удаление попадает в очередь обновлений
+    pub fn delete_posts(&self, chat_id: i64, ids: &[i32]) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(posts) = state.posts.get_mut(&chat_id) {
+            posts.retain(|post| !ids.contains(&post.id));
+        }
+        state.updates.push_back(ChatUpdate::DeletedPosts {
+            chat_id,
+            ids: ids.to_vec(),
+        });
+        drop(state);
+
+        self.updates_ready.notify_one();
+    }
+
+    /// Медиа с меткой `media` скачивается как картинка
+    pub fn add_image(&self, media: &str, image: MediaFile) {
+        self.state.lock().unwrap().images.insert(media.to_string(), image);
+    }
+
+    /// Следующая отправка завершится этой ошибкой
+    pub fn fail_next_send(&self, error: TransportError) {
+        self.state.lock().unwrap().send_errors.push(error);
+    }
+
+    /// Всё, что было отправлено, в порядке отправки
+    pub fn sent(&self) -> Vec<SentPost> {
+        self.state.lock().unwrap().sent.clone()
+    }
+
+    fn record(&self, post: SentPost, count: usize) -> Result<Vec<i32>> {
+        let mut state = self.state.lock().unwrap();
+        if !state.send_errors.is_empty() {
+            return Err(state.send_errors.remove(0));
+        }
+        state.sent.push(post);
+
+        let first = state.next_sent_id + 1;
+        state.next_sent_id += count as i32;
+        Ok((first..=state.next_sent_id).collect())
+    }
+}
+
+impl ChatTransport for FakeTransport {
+    type Media = String;
+
+    async fn resolve_username(&self, username: &str) -> Result<Option<ResolvedChat>> {
+        Ok(self.state.lock().unwrap().chats.get(username).cloned())
+    }
+
+    async fn history_page(
+        &self,
+        chat_id: i64,
+        offset_id: Option<i32>,
+        limit: usize,
+    ) -> Result<Vec<SourcePost<String>>> {
+        let state = self.state.lock().unwrap();
+        let posts = state.posts.get(&chat_id).map(Vec::as_slice).unwrap_or_default();
+        Ok(posts
+            .iter()
+            .rev()
+            .filter(|post| offset_id.is_none_or(|offset| post.id < offset))
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    async fn posts_by_id(&self, chat_id: i64, ids: &[i32]) -> Result<Vec<SourcePost<String>>> {
+        let state = self.state.lock().unwrap();
+        let posts = state.posts.get(&chat_id).map(Vec::as_slice).unwrap_or_default();
+        Ok(posts.iter().filter(|post| ids.contains(&post.id)).cloned().collect())
+    }
+
+    async fn download_image(&self, media: &String, max_bytes: usize) -> Result<Option<MediaFile>> {
+        let state = self.state.lock().unwrap();
+        Ok(state.images.get(media).filter(|image| image.bytes.len() <= max_bytes).cloned())
+    }
+
+    async fn next_update(&self) -> Result<ChatUpdate<String>> {
+        loop {
+            let update = self.state.lock().unwrap().updates.pop_front();
+            if let Some(update) = update {
+                return Ok(update);
+            }
+            self.updates_ready.notified().await;
+        }
+    }
+
+    fn text_len(&self, markup: &str) -> usize {
+        split::utf16_len(markup)
+    }
+
+    fn split_text(&self, markup: &str, first_limit: usize, limit: usize) -> Vec<String> {
+        split::split(&PlainText, markup, first_limit, limit)
+    }
+
+    async fn send_text(&self, chat_id: i64, text: &str, reply_to: Option<i32>, link_preview: bool) -> Result<i32> {
+        let post = SentPost::Text {
+            chat_id,
+            text: text.to_string(),
+            reply_to,
+            link_preview,
+        };
+        Ok(self.record(post, 1)?[0])
+    }
+
+    async fn send_media(&self, chat_id: i64, media: &String, caption: &str) -> Result<i32> {
+        let post = SentPost::Media {
+            chat_id,
+            media: media.clone(),
+            caption: caption.to_string(),
+        };
+        Ok(self.record(post, 1)?[0])
+    }
+
+    async fn send_album(&self, chat_id: i64, items: Vec<AlbumItem<String>>) -> Result<Vec<i32>> {
+        let count = items.len();
+        let post = SentPost::Album {
+            chat_id,
+            items: items.into_iter().map(|i| (i.caption, i.media)).collect(),
+        };
+        self.record(post, count)
+    }
+
+    async fn edit_text(&self, chat_id: i64, id: i32, text: &str, link_preview: bool) -> Result<()> {
+        let post = SentPost::EditText {
+            chat_id,
+            id,
+            text: text.to_string(),
+            link_preview,
+        };
+        self.record(post, 0).map(drop)
+    }
+
+    async fn edit_media(&self, chat_id: i64, id: i32, media: &String, caption: &str) -> Result<()> {
+        let post = SentPost::EditMedia {
+            chat_id,
+            id,
+            media: media.clone(),
+            caption: caption.to_string(),
+        };
+        self.record(post, 0).map(drop)
+    }
+
+    async fn delete(&self, chat_id: i64, ids: &[i32]) -> Result<()> {
+        let post = SentPost::Delete {
+            chat_id,
+            ids: ids.to_vec(),
+        };
+        self.record(post, 0).map(drop)
+    }
+
+    async fn forward(&self, chat_id: i64, from_chat_id: i64, ids: &[i32]) -> Result<Vec<i32>> {
+        let post = SentPost::Forward {
+            chat_id,
+            from_chat_id,
+            ids: ids.to_vec(),
+        };
+        self.record(post, ids.len())
+    }
+}
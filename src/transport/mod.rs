@@ -0,0 +1,202 @@
+//! Абстракция над Telegram I/O.
+//!
+//! Вся работа с клиентом Telegram (чтение постов источников, отправка в target)
+//! идёт через [`ChatTransport`]. Реальная реализация — [`telegram::TelegramTransport`],
+//! поверх grammers. В тестах её заменяет `fake::FakeTransport`: он хранит всё в памяти
+//! и позволяет проверять логику репоста без живого аккаунта.
+
+use std::{fmt, future::Future};
+
+use crate::bot::types::PostKind;
+
+#[cfg(test)]
+pub mod fake;
+mod reupload;
+pub mod split;
+pub mod telegram;
+
+pub type Result<T> = std::result::Result<T, TransportError>;
+
+/// Ошибка транспорта
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransportError {
+    /// Telegram попросил подождать указанное число секунд
+    FloodWait(u32),
+    /// Прочая RPC ошибка, имя ошибки от сервера
+    Rpc(String),
+    /// Чат не был предварительно разрешён через `resolve_username`
+    UnknownChat(i64),
+    /// Сетевые/внутренние ошибки клиента
+    Other(String),
+    /// Повтор не поможет: например, вложение нельзя перезалить или оно больше лимита
+    Permanent(String),
+}
+
+impl fmt::Display for TransportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransportError::FloodWait(secs) => write!(f, "FLOOD_WAIT for {} seconds", secs),
+            TransportError::Rpc(name) => write!(f, "RPC error: {}", name),
+            TransportError::UnknownChat(id) => write!(f, "chat {} was not resolved", id),
+            TransportError::Other(e) | TransportError::Permanent(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for TransportError {}
+
+/// Разрешённый по username чат
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedChat {
+    pub id: i64,
+    pub name: String,
+}
+
+/// Пост из канала-источника
+#[derive(Debug, Clone)]
+pub struct SourcePost<M> {
+    pub chat_id: i64,
+    pub id: i32,
+    /// grouped_id альбома, если пост входит в альбом
+    pub grouped_id: Option<i64>,
+    /// Unix timestamp публикации
+    pub date: i64,
+    /// Unix timestamp последней правки, `None` — пост не правился
+    pub edit_date: Option<i64>,
+    /// Текст без форматирования
+    pub text: String,
+    /// Тот же текст с форматированием в разметке транспорта (`bot_settings.text_format`)
+    pub markup: String,
+    pub kind: PostKind,
+    pub media: Option<M>,
+}
+
+/// Элемент альбома на отправку
+#[derive(Debug, Clone)]
+pub struct AlbumItem<M> {
+    /// Подпись в разметке транспорта
+    pub caption: String,
+    pub media: M,
+}
+
+/// Скачанный файл вложения
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MediaFile {
+    /// Например `image/jpeg`
+    pub mime: String,
+    pub bytes: Vec<u8>,
+}
+
+/// Входящее обновление от Telegram
+#[derive(Debug, Clone)]
+pub enum ChatUpdate<M> {
+    /// Новый пост в одном из чатов аккаунта
+    NewPost(SourcePost<M>),
+    /// Пост отредактирован, новое содержимое
+    EditedPost(SourcePost<M>),
+    /// Посты канала удалены
+    DeletedPosts { chat_id: i64, ids: Vec<i32> },
+    /// Всё, что бот не обрабатывает
+    Other,
+}
+
+/// Операции с Telegram, которые нужны боту
+pub trait ChatTransport: Send + Sync + 'static {
+    /// Медиа вложение поста. Для grammers это `Media`, у фейка — просто метка.
+    type Media: Clone + fmt::Debug + Send + Sync + 'static;
+
+    /// Находим чат по username и запоминаем его для последующих вызовов
+    fn resolve_username(
+        &self,
+        username: &str,
+    ) -> impl Future<Output = Result<Option<ResolvedChat>>> + Send;
+
+    /// Страница истории чата: до `limit` постов с id меньше `offset_id`, от новых к старым.
+    /// Без `offset_id` — самые последние посты
+    fn history_page(
+        &self,
+        chat_id: i64,
+        offset_id: Option<i32>,
+        limit: usize,
+    ) -> impl Future<Output = Result<Vec<SourcePost<Self::Media>>>> + Send;
+
+    /// Посты чата по id. Удалённые посты в результат не попадают
+    fn posts_by_id(
+        &self,
+        chat_id: i64,
+        ids: &[i32],
+    ) -> impl Future<Output = Result<Vec<SourcePost<Self::Media>>>> + Send;
+
+    /// Скачиваем картинку вложения: фото или документ-изображение.
+    /// `None`, если вложение не картинка или больше `max_bytes`
+    fn download_image(
+        &self,
+        media: &Self::Media,
+        max_bytes: usize,
+    ) -> impl Future<Output = Result<Option<MediaFile>>> + Send;
+
+    /// Ждём следующее обновление
+    fn next_update(&self) -> impl Future<Output = Result<ChatUpdate<Self::Media>>> + Send;
+
+    /// Длина текста в разметке транспорта без самой разметки, в UTF-16 как у Telegram
+    fn text_len(&self, markup: &str) -> usize;
+
+    /// Делим текст в разметке транспорта, не разрывая форматирование:
+    /// первая часть не длиннее `first_limit`, остальные — не длиннее `limit`
+    fn split_text(&self, markup: &str, first_limit: usize, limit: usize) -> Vec<String>;
+
+    /// Отправляем текст в разметке транспорта, возвращаем id отправленного сообщения.
+    /// `reply_to` — ответом на это сообщение того же чата, `link_preview` — с превью ссылки
+    fn send_text(
+        &self,
+        chat_id: i64,
+        text: &str,
+        reply_to: Option<i32>,
+        link_preview: bool,
+    ) -> impl Future<Output = Result<i32>> + Send;
+
+    /// Копируем одно вложение любого вида с подписью в разметке транспорта
+    fn send_media(
+        &self,
+        chat_id: i64,
+        media: &Self::Media,
+        caption: &str,
+    ) -> impl Future<Output = Result<i32>> + Send;
+
+    /// Отправляем альбом (или одиночное медиа), возвращаем id отправленных сообщений
+    fn send_album(
+        &self,
+        chat_id: i64,
+        items: Vec<AlbumItem<Self::Media>>,
+    ) -> impl Future<Output = Result<Vec<i32>>> + Send;
+
+    /// Меняем текст отправленного сообщения. Сообщение без изменений — не ошибка
+    fn edit_text(
+        &self,
+        chat_id: i64,
+        id: i32,
+        text: &str,
+        link_preview: bool,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    /// Меняем вложение и подпись отправленного сообщения. Сообщение без изменений — не ошибка
+    fn edit_media(
+        &self,
+        chat_id: i64,
+        id: i32,
+        media: &Self::Media,
+        caption: &str,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    /// Удаляем отправленные сообщения
+    fn delete(&self, chat_id: i64, ids: &[i32]) -> impl Future<Output = Result<()>> + Send;
+
+    /// Пересылаем посты `ids` из `from_chat_id` с заголовком «Переслано из».
+    /// Посты одного альбома, пересланные вместе, остаются альбомом
+    fn forward(
+        &self,
+        chat_id: i64,
+        from_chat_id: i64,
+        ids: &[i32],
+    ) -> impl Future<Output = Result<Vec<i32>>> + Send;
+}
@@ -0,0 +1,427 @@
+//! Перезаливка медиа из каналов с запретом пересылки и сохранения.
+//!
+//! `copy_media` для таких каналов падает с `CHAT_FORWARDS_RESTRICTED`. Тогда
+//! вложение скачивается во временный файл, загружается заново через
+//! `upload_file` и отправляется с атрибутами оригинала (длительность, размеры,
+//! имя файла). Временный файл удаляется сразу после загрузки, в том числе при
+//! ошибке. У каждого процесса свой подкаталог: остатки от аварийного завершения
+//! чистятся при старте, не задевая загрузки других запущенных экземпляров.
+//!
+//! Стикеры и GIF не перезаливаются: grammers не даёт передать атрибуты стикера
+//! и анимации, без них Telegram показал бы их файлом и видео.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use grammers_client::{
+    grammers_tl_types as tl,
+    types::{media::Uploaded, Attribute, Downloadable, Media},
+    Client, InputMedia, InputMessage, InvocationError,
+};
+use tokio::{fs, io::AsyncWriteExt};
+
+use crate::{bot::types::PostKind, config::ReuploadConfig, log_warn};
+
+use super::{telegram::document_kind, Result, TransportError};
+
+/// Источник запретил копировать свои посты
+pub fn is_forwards_restricted(e: &InvocationError) -> bool {
+    matches!(e, InvocationError::Rpc(rpc) if rpc.name == "CHAT_FORWARDS_RESTRICTED")
+}
+
+/// Загруженное заново вложение
+pub enum Reuploaded {
+    Photo(Uploaded),
+    Document {
+        uploaded: Uploaded,
+        mime: String,
+        attributes: Vec<Attribute>,
+    },
+}
+
+impl Reuploaded {
+    pub fn attach_to_message(self, message: InputMessage) -> InputMessage {
+        match self {
+            Reuploaded::Photo(uploaded) => message.photo(uploaded),
+            Reuploaded::Document {
+                uploaded,
+                mime,
+                attributes,
+            } => attributes
+                .into_iter()
+                .fold(message.document(uploaded).mime_type(&mime), InputMessage::attribute),
+        }
+    }
+
+    pub fn attach_to_media(self, media: InputMedia) -> InputMedia {
+        match self {
+            Reuploaded::Photo(uploaded) => media.photo(uploaded),
+            Reuploaded::Document {
+                uploaded,
+                mime,
+                attributes,
+            } => attributes
+                .into_iter()
+                .fold(media.document(uploaded).mime_type(&mime), InputMedia::attribute),
+        }
+    }
+}
+
+pub struct Reuploader {
+    config: ReuploadConfig,
+    temp_dir: PathBuf,
+    /// Счётчик для уникальных имён временных файлов
+    next_file: AtomicU64,
+}
+
+impl Reuploader {
+    pub fn new(config: ReuploadConfig) -> Self {
+        let temp_dir = config.temp_dir().join(std::process::id().to_string());
+        remove_leftovers(&temp_dir);
+        Self {
+            config,
+            temp_dir,
+            next_file: AtomicU64::new(0),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    /// Скачиваем `media` во временный файл и загружаем заново.
+    /// Вложения без файла (опросы, гео, контакты) перезалить нельзя
+    pub async fn reupload(&self, client: &Client, media: &Media) -> Result<Reuploaded> {
+        let original = match media {
+            Media::Photo(_) => Original::photo(),
+            Media::Document(document) => describe(&document.raw)?,
+            Media::Sticker(sticker) => describe(&sticker.document.raw)?,
+            _ => return Err(not_reuploadable("media without a file")),
+        };
+        let max_bytes = self.check(&original)?;
+
+        let file = self.temp_file().await?;
+        let mut download = client.iter_download(&Downloadable::Media(media.clone()));
+        let mut output = fs::File::create(file.path()).await.map_err(io_error)?;
+        let mut written = 0u64;
+        while let Some(chunk) = download.next().await? {
+            written += chunk.len() as u64;
+            // Размер документа известен заранее, у фото — нет
+            if written > max_bytes {
+                return Err(too_large(&original.kind, max_bytes));
+            }
+            output.write_all(&chunk).await.map_err(io_error)?;
+        }
+        output.flush().await.map_err(io_error)?;
+        drop(output);
+
+        let uploaded = client.upload_file(file.path()).await.map_err(io_error)?;
+        Ok(match original.mime {
+            None => Reuploaded::Photo(uploaded),
+            Some(mime) => Reuploaded::Document {
+                uploaded,
+                mime,
+                attributes: original.attributes,
+            },
+        })
+    }
+
+    /// Можно ли перезалить вложение, и если да — лимит его размера
+    fn check(&self, original: &Original) -> Result<u64> {
+        let limits = &self.config.max_bytes;
+        let max_bytes = match original.kind {
+            PostKind::Sticker { .. } | PostKind::Animation => {
+                return Err(not_reuploadable(&format!("{:?}", original.kind)));
+            }
+            PostKind::Photo => limits.photo,
+            PostKind::Video => limits.video,
+            PostKind::Audio { .. } => limits.audio,
+            PostKind::Voice => limits.voice,
+            PostKind::VideoNote => limits.video_note,
+            _ => limits.document,
+        };
+        if original.size.is_some_and(|size| size > max_bytes) {
+            return Err(too_large(&original.kind, max_bytes));
+        }
+        Ok(max_bytes)
+    }
+
+    async fn temp_file(&self) -> Result<TempFile> {
+        fs::create_dir_all(&self.temp_dir).await.map_err(io_error)?;
+        let index = self.next_file.fetch_add(1, Ordering::Relaxed);
+        Ok(TempFile(self.temp_dir.join(format!("media-{}", index))))
+    }
+}
+
+impl Drop for Reuploader {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir(&self.temp_dir);
+    }
+}
+
+/// Что нужно знать об оригинале для повторной загрузки
+struct Original {
+    kind: PostKind,
+    /// Размер файла, у фото заранее неизвестен
+    size: Option<u64>,
+    /// MIME документа, `None` — фото
+    mime: Option<String>,
+    attributes: Vec<Attribute>,
+}
+
+impl Original {
+    fn photo() -> Self {
+        Self {
+            kind: PostKind::Photo,
+            size: None,
+            mime: None,
+            attributes: Vec::new(),
+        }
+    }
+}
+
+fn describe(media: &tl::types::MessageMediaDocument) -> Result<Original> {
+    let Some(tl::enums::Document::Document(document)) = &media.document else {
+        return Err(TransportError::Other("document is empty".to_string()));
+    };
+    Ok(Original {
+        kind: document_kind(media),
+        size: Some(document.size.max(0) as u64),
+        mime: Some(document.mime_type.clone()),
+        attributes: document.attributes.iter().filter_map(attribute).collect(),
+    })
+}
+
+/// Атрибут оригинала в виде, который принимает `InputMessage`. Атрибутов стикера
+/// и анимации в нём нет, такие вложения [`Reuploader::check`] не пропускает
+fn attribute(attribute: &tl::enums::DocumentAttribute) -> Option<Attribute> {
+    use tl::enums::DocumentAttribute;
+
+    match attribute {
+        DocumentAttribute::Filename(attribute) => Some(Attribute::FileName(attribute.file_name.clone())),
+        DocumentAttribute::Audio(attribute) if attribute.voice => Some(Attribute::Voice {
+            duration: Duration::from_secs(attribute.duration.max(0) as u64),
+            waveform: attribute.waveform.clone(),
+        }),
+        DocumentAttribute::Audio(attribute) => Some(Attribute::Audio {
+            duration: Duration::from_secs(attribute.duration.max(0) as u64),
+            title: attribute.title.clone(),
+            performer: attribute.performer.clone(),
+        }),
+        DocumentAttribute::Video(attribute) => Some(Attribute::Video {
+            round_message: attribute.round_message,
+            supports_streaming: attribute.supports_streaming,
+            duration: Duration::from_secs_f64(attribute.duration.max(0.0)),
+            w: attribute.w,
+            h: attribute.h,
+        }),
+        _ => None,
+    }
+}
+
+fn too_large(kind: &PostKind, max_bytes: u64) -> TransportError {
+    TransportError::Permanent(format!("{:?} is larger than {} bytes, not re-uploaded", kind, max_bytes))
+}
+
+fn not_reuploadable(what: &str) -> TransportError {
+    TransportError::Permanent(format!("CHAT_FORWARDS_RESTRICTED: {} can't be re-uploaded", what))
+}
+
+fn io_error(e: std::io::Error) -> TransportError {
+    TransportError::Other(e.to_string())
+}
+
+/// Временный файл, удаляется при выходе из области видимости
+struct TempFile(PathBuf);
+
+impl TempFile {
+    fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        match std::fs::remove_file(&self.0) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                log_warn!("Failed to remove temp file {}: {}", self.0.display(), e);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Файлы, оставшиеся после аварийного завершения процесса с тем же id
+fn remove_leftovers(dir: &Path) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let _ = std::fs::remove_file(entry.path());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tl::enums::DocumentAttribute;
+
+    use super::*;
+    use crate::config::MediaSizeLimits;
+
+    fn document(mime: &str, size: i64, attributes: Vec<DocumentAttribute>) -> tl::types::MessageMediaDocument {
+        let document = tl::types::Document {
+            id: 1,
+            access_hash: 0,
+            file_reference: Vec::new(),
+            date: 0,
+            mime_type: mime.to_string(),
+            size,
+            thumbs: None,
+            video_thumbs: None,
+            dc_id: 2,
+            attributes,
+        };
+        tl::types::MessageMediaDocument {
+            nopremium: false,
+            spoiler: false,
+            video: false,
+            round: false,
+            voice: false,
+            document: Some(document.into()),
+            alt_document: None,
+            ttl_seconds: None,
+        }
+    }
+
+    fn video(round_message: bool) -> DocumentAttribute {
+        tl::types::DocumentAttributeVideo {
+            round_message,
+            supports_streaming: true,
+            nosound: false,
+            duration: 12.5,
+            w: 640,
+            h: 480,
+            preload_prefix_size: None,
+            video_start_ts: None,
+        }
+        .into()
+    }
+
+    fn audio(voice: bool) -> DocumentAttribute {
+        tl::types::DocumentAttributeAudio {
+            voice,
+            duration: 3,
+            title: Some("Title".to_string()),
+            performer: Some("Performer".to_string()),
+            waveform: Some(vec![1, 2, 3]),
+        }
+        .into()
+    }
+
+    fn file_name(name: &str) -> DocumentAttribute {
+        tl::types::DocumentAttributeFilename {
+            file_name: name.to_string(),
+        }
+        .into()
+    }
+
+    fn sticker() -> DocumentAttribute {
+        tl::types::DocumentAttributeSticker {
+            mask: false,
+            alt: "🙂".to_string(),
+            stickerset: tl::enums::InputStickerSet::Empty,
+            mask_coords: None,
+        }
+        .into()
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("zad-reupload-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn reuploader(dir: &Path) -> Reuploader {
+        Reuploader::new(ReuploadConfig {
+            temp_dir: Some(dir.to_string_lossy().into_owned()),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn kind_selects_size_limit() {
+        let limits = MediaSizeLimits::default();
+        let cases = [
+            ("video", document("video/mp4", 1, vec![video(false)]), Some(limits.video)),
+            ("video note", document("video/mp4", 1, vec![video(true)]), Some(limits.video_note)),
+            ("voice", document("audio/ogg", 1, vec![audio(true)]), Some(limits.voice)),
+            ("audio", document("audio/mpeg", 1, vec![audio(false)]), Some(limits.audio)),
+            ("file", document("application/pdf", 1, vec![file_name("a.pdf")]), Some(limits.document)),
+            ("gif", document("video/mp4", 1, vec![video(false), DocumentAttribute::Animated]), None),
+            ("sticker", document("image/webp", 1, vec![sticker()]), None),
+            ("too large", document("application/pdf", limits.document as i64 + 1, Vec::new()), None),
+        ];
+
+        let dir = temp_dir("limits");
+        let reuploader = reuploader(&dir);
+        assert_eq!(reuploader.check(&Original::photo()).unwrap(), limits.photo);
+        for (name, media, expected) in cases {
+            let original = describe(&media).unwrap();
+            match (reuploader.check(&original), expected) {
+                (Ok(max_bytes), Some(expected)) => assert_eq!(max_bytes, expected, "{}", name),
+                (Err(e), None) => assert!(matches!(e, TransportError::Permanent(_)), "{}: {}", name, e),
+                (result, _) => panic!("{}: unexpected {:?}", name, result),
+            }
+        }
+    }
+
+    #[test]
+    fn original_attributes_are_kept() {
+        let media = document("video/mp4", 10, vec![file_name("clip.mp4"), video(true), audio(true), audio(false)]);
+        let original = describe(&media).unwrap();
+        assert_eq!(original.mime.as_deref(), Some("video/mp4"));
+        assert_eq!(original.size, Some(10));
+
+        let [name, video, voice, audio] = original.attributes.as_slice() else {
+            panic!("expected 4 attributes, got {}", original.attributes.len());
+        };
+        assert!(matches!(name, Attribute::FileName(name) if name == "clip.mp4"));
+        assert!(matches!(
+            video,
+            Attribute::Video { round_message: true, supports_streaming: true, duration, w: 640, h: 480 }
+                if *duration == Duration::from_millis(12_500)
+        ));
+        assert!(matches!(
+            voice,
+            Attribute::Voice { duration, waveform: Some(waveform) } if duration.as_secs() == 3 && *waveform == [1, 2, 3]
+        ));
+        assert!(matches!(
+            audio,
+            Attribute::Audio { title: Some(title), performer: Some(performer), .. }
+                if title == "Title" && performer == "Performer"
+        ));
+        assert!(attribute(&sticker()).is_none());
+        assert!(attribute(&DocumentAttribute::Animated).is_none());
+    }
+
+    #[test]
+    fn leftovers_are_removed_only_from_own_directory() {
+        let dir = temp_dir("leftovers");
+        let own = dir.join(std::process::id().to_string());
+        let other = dir.join("other");
+        for dir in [&own, &other] {
+            std::fs::create_dir_all(dir).unwrap();
+            std::fs::write(dir.join("media-0"), b"partial").unwrap();
+        }
+
+        let reuploader = reuploader(&dir);
+        assert!(!own.join("media-0").exists());
+        assert!(other.join("media-0").exists());
+
+        drop(reuploader);
+        assert!(!own.exists());
+    }
+}
@@ -0,0 +1,259 @@
+//! Деление длинных текстов по лимитам Telegram без разрыва форматирования.
+//!
+//! Текст режется по переносам абзацев, строк или пробелам. Разрез допустим,
+//! только если ни одна сущность форматирования не оказывается по обе его стороны:
+//! у канонической разметки это значит, что голова до разреза уже канонична.
+
+/// Лимит текста сообщения
+pub const TEXT_LIMIT: usize = 4096;
+/// Лимит подписи к медиа
+pub const CAPTION_LIMIT: usize = 1024;
+
+/// Разметка текста (HTML, Markdown или её отсутствие)
+pub trait Markup {
+    /// Текст без разметки
+    fn plain(&self, markup: &str) -> String;
+    /// Та же разметка в каноническом виде
+    fn canonical(&self, markup: &str) -> String;
+    /// Разметка для текста без форматирования
+    fn escape(&self, text: &str) -> String;
+}
+
+/// Длина так, как её считает Telegram, в UTF-16
+pub fn utf16_len(text: &str) -> usize {
+    text.encode_utf16().count()
+}
+
+/// Первая часть не длиннее `first_limit`, остальные — не длиннее `limit`
+pub fn split(markup: &impl Markup, text: &str, first_limit: usize, limit: usize) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut rest = markup.canonical(text);
+    loop {
+        let limit = if parts.is_empty() { first_limit } else { limit };
+        if utf16_len(&markup.plain(&rest)) <= limit {
+            parts.push(rest);
+            return parts;
+        }
+
+        let (head, tail) = match cut(markup, &rest, limit) {
+            Some(at) => (rest[..at].trim_end().to_string(), rest[at..].trim_start().to_string()),
+            // Подходящего разреза нет: режем текст посередине слова, форматирование головы теряется
+            None => {
+                let plain = markup.plain(&rest);
+                let at = char_boundary(&plain, limit).max(plain.chars().next().map_or(0, char::len_utf8));
+                (markup.escape(&plain[..at]), tail_markup(markup, &rest, &plain[at..]))
+            }
+        };
+        // Части из одних пробелов не отправляем
+        if !markup.plain(&head).trim().is_empty() {
+            parts.push(head);
+        }
+        if markup.plain(&tail).trim().is_empty() {
+            return parts;
+        }
+        rest = tail;
+    }
+}
+
+/// Где резать `text`, чтобы голова влезла в `limit`. Переносы абзацев лучше
+/// переносов строк, те — пробелов, но не ценой слишком короткой головы
+fn cut(markup: &impl Markup, text: &str, limit: usize) -> Option<usize> {
+    let mut fallback = None;
+    for separator in ["\n\n", "\n", " "] {
+        let Some(at) = last_valid_cut(markup, text, separator, limit) else {
+            continue;
+        };
+        if utf16_len(&markup.plain(&text[..at])) >= limit / 2 {
+            return Some(at);
+        }
+        fallback = fallback.max(Some(at));
+    }
+    fallback
+}
+
+/// Последний разрез после `separator`, с которым голова влезает в `limit`
+/// и форматирование не разорвано
+fn last_valid_cut(markup: &impl Markup, text: &str, separator: &str, limit: usize) -> Option<usize> {
+    let candidates: Vec<usize> = text
+        .match_indices(separator)
+        .map(|(index, separator)| index + separator.len())
+        .filter(|&at| at < text.len())
+        .collect();
+    // Длина головы растёт вместе с позицией разреза
+    let fits = candidates.partition_point(|&at| utf16_len(&markup.plain(&text[..at])) <= limit);
+
+    candidates[..fits].iter().rev().copied().find(|&at| markup.canonical(&text[..at]) == text[..at])
+}
+
+/// Разметка хвоста `text`, текст которого — `plain_tail`. Сущность, попавшая
+/// под разрез, теряется, остальное форматирование хвоста сохраняется
+fn tail_markup(markup: &impl Markup, text: &str, plain_tail: &str) -> String {
+    let plain_head = markup.plain(text).len() - plain_tail.len();
+    let boundaries: Vec<usize> = text.char_indices().map(|(index, _)| index).collect();
+    // Первая позиция, до которой уже весь текст головы
+    let first = boundaries.partition_point(|&at| markup.plain(&text[..at]).len() < plain_head);
+    let tail = boundaries.get(first).map_or("", |&at| &text[at..]);
+
+    let tail = markup.canonical(tail);
+    if markup.plain(&tail) == plain_tail {
+        tail
+    } else {
+        markup.escape(plain_tail)
+    }
+}
+
+/// Наибольшая граница символа, до которой в `text` не больше `limit` единиц UTF-16
+fn char_boundary(text: &str, limit: usize) -> usize {
+    let mut units = 0;
+    for (index, c) in text.char_indices() {
+        units += c.len_utf16();
+        if units > limit {
+            return index;
+        }
+    }
+    text.len()
+}
+
+/// Текст без разметки, для фейкового транспорта
+#[cfg(test)]
+pub struct PlainText;
+
+#[cfg(test)]
+impl Markup for PlainText {
+    fn plain(&self, markup: &str) -> String {
+        markup.to_string()
+    }
+
+    fn canonical(&self, markup: &str) -> String {
+        markup.to_string()
+    }
+
+    fn escape(&self, text: &str) -> String {
+        text.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Упрощённый HTML: теги без атрибутов. Как и разбор с генерацией у grammers,
+    /// канонический вид закрывает незакрытые теги и отбрасывает лишние закрывающие
+    struct TestHtml;
+
+    impl Markup for TestHtml {
+        fn plain(&self, markup: &str) -> String {
+            let mut plain = String::new();
+            let mut in_tag = false;
+            for c in markup.chars() {
+                match c {
+                    '<' => in_tag = true,
+                    '>' => in_tag = false,
+                    c if !in_tag => plain.push(c),
+                    _ => {}
+                }
+            }
+            plain.replace("&lt;", "<").replace("&gt;", ">").replace("&amp;", "&")
+        }
+
+        fn canonical(&self, markup: &str) -> String {
+            let mut canonical = String::new();
+            let mut open = Vec::new();
+            let mut rest = markup;
+            while let Some(start) = rest.find('<') {
+                canonical.push_str(&rest[..start]);
+                let end = start + rest[start..].find('>').expect("unclosed tag");
+                let tag = &rest[start + 1..end];
+                match tag.strip_prefix('/') {
+                    Some(name) if open.last() == Some(&name) => {
+                        open.pop();
+                        canonical.push_str(&rest[start..=end]);
+                    }
+                    Some(_) => {}
+                    None => {
+                        open.push(tag);
+                        canonical.push_str(&rest[start..=end]);
+                    }
+                }
+                rest = &rest[end + 1..];
+            }
+            canonical.push_str(rest);
+            for tag in open.iter().rev() {
+                canonical.push_str(&format!("</{}>", tag));
+            }
+            canonical
+        }
+
+        fn escape(&self, text: &str) -> String {
+            text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+        }
+    }
+
+    #[test]
+    fn utf16_len_counts_surrogate_pairs() {
+        assert_eq!(utf16_len("abc"), 3);
+        assert_eq!(utf16_len("абв"), 3);
+        assert_eq!(utf16_len("😀"), 2);
+        assert_eq!(utf16_len("a😀б"), 4);
+    }
+
+    #[test]
+    fn plain_text() {
+        let cases: [(&str, &str, usize, usize, &[&str]); 9] = [
+            ("fits", "short", 10, 10, &["short"]),
+            ("words", "aa bb cc dd", 6, 6, &["aa bb", "cc dd"]),
+            ("separator counts", "aa bb cc dd", 5, 5, &["aa", "bb", "cc dd"]),
+            ("first limit", "aa bb cc dd", 3, 10, &["aa", "bb cc dd"]),
+            ("paragraph", "aaaa bbbb\n\ncccc dddd", 12, 12, &["aaaa bbbb", "cccc dddd"]),
+            ("short paragraph", "aaaa\n\nbbbb cccc dddd", 15, 15, &["aaaa\n\nbbbb", "cccc dddd"]),
+            ("emoji count twice", "😀 😀 😀 😀 😀", 6, 6, &["😀 😀", "😀 😀", "😀"]),
+            ("no separator", "abcdefghij", 4, 4, &["abcd", "efgh", "ij"]),
+            ("no separator emoji", "😀😀😀", 3, 3, &["😀", "😀", "😀"]),
+        ];
+        for (name, text, first_limit, limit, expected) in cases {
+            assert_eq!(split(&PlainText, text, first_limit, limit), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn plain_text_parts_fit_limits() {
+        let text = "Слово 😀 ".repeat(700);
+        let parts = split(&PlainText, &text, CAPTION_LIMIT, TEXT_LIMIT);
+        assert!(parts.len() > 1);
+        assert!(utf16_len(&parts[0]) <= CAPTION_LIMIT);
+        assert!(parts.iter().all(|part| utf16_len(part) <= TEXT_LIMIT));
+        assert_eq!(parts.join(" ").split_whitespace().count(), text.split_whitespace().count());
+    }
+
+    #[test]
+    fn hard_cut_keeps_characters_and_drops_blank_parts() {
+        assert_eq!(split(&PlainText, "😀😀", 1, 1), ["😀", "😀"]);
+        assert_eq!(split(&PlainText, "aaaa      ", 4, 4), ["aaaa"]);
+    }
+
+    #[test]
+    fn html() {
+        let cases: [(&str, &str, usize, &[&str]); 8] = [
+            ("fits", "<b>bold</b> text", 20, &["<b>bold</b> text"]),
+            ("after entity", "<b>aa bb</b> cc dd", 8, &["<b>aa bb</b>", "cc dd"]),
+            ("around entity", "aa <b>bb cc</b> dd", 7, &["aa", "<b>bb cc</b>", "dd"]),
+            ("nested", "<b>aa <i>bb</i></b> cc", 6, &["<b>aa <i>bb</i></b>", "cc"]),
+            ("tags not counted", "<b>😀😀</b> 😀", 5, &["<b>😀😀</b>", "😀"]),
+            ("no safe cut", "<b>aaaa bbbb</b>", 5, &["aaaa ", "bbbb"]),
+            ("hard cut keeps tail markup", "<b>aaaaaa</b><i>bb</i>", 4, &["aaaa", "aa<i>bb</i>"]),
+            ("hard cut before entity", "aaaaaa<i>bb</i>", 6, &["aaaaaa", "<i>bb</i>"]),
+        ];
+        for (name, text, limit, expected) in cases {
+            assert_eq!(split(&TestHtml, text, limit, limit), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn html_parts_keep_entities_whole() {
+        let text = "<b>жирный текст</b> обычный <i>курсив тоже</i> и ещё <code>код без пробелов</code> ".repeat(20);
+        for part in split(&TestHtml, &text, 60, 60) {
+            assert!(utf16_len(&TestHtml.plain(&part)) <= 60, "{}", part);
+            assert_eq!(TestHtml.canonical(&part), part);
+        }
+    }
+}
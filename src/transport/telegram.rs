@@ -0,0 +1,403 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use grammers_client::{
+    grammers_tl_types as tl,
+    parsers::{generate_html_message, generate_markdown_message, parse_html_message, parse_markdown_message},
+    types::{Downloadable, Media, Message, PackedChat, Update},
+    Client, InputMedia, InputMessage, InvocationError,
+};
+
+use crate::{
+    bot::types::PostKind,
+    config::{ReuploadConfig, TextFormat},
+};
+
+use super::{
+    reupload::{is_forwards_restricted, Reuploader},
+    split::{self, Markup},
+    AlbumItem, ChatTransport, ChatUpdate, MediaFile, ResolvedChat, Result, SourcePost, TransportError,
+};
+
+/// Документы, которые модель примет как картинку
+const IMAGE_MIME_TYPES: [&str; 3] = ["image/jpeg", "image/png", "image/webp"];
+
+/// Транспорт поверх клиента grammers
+pub struct TelegramTransport {
+    client: Client,
+    /// Разрешённые чаты: chat_id -> PackedChat
+    chats: Mutex<HashMap<i64, PackedChat>>,
+    /// Разметка `SourcePost::markup` и отправляемых текстов
+    format: TextFormat,
+    /// Запасной путь для медиа из каналов, запретивших копирование
+    reuploader: Reuploader,
+}
+
+impl TelegramTransport {
+    pub fn new(client: Client, format: TextFormat, reupload: ReuploadConfig) -> Self {
+        Self {
+            client,
+            chats: Mutex::new(HashMap::new()),
+            format,
+            reuploader: Reuploader::new(reupload),
+        }
+    }
+
+    /// Клиент, на случай если нужен прямой доступ (get_me, сессия)
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    fn packed(&self, chat_id: i64) -> Result<PackedChat> {
+        self.chats
+            .lock()
+            .unwrap()
+            .get(&chat_id)
+            .copied()
+            .ok_or(TransportError::UnknownChat(chat_id))
+    }
+
+    fn to_post(&self, message: &Message) -> SourcePost<Media> {
+        let text = message.text();
+        let markup = generate(self.format, text, message.fmt_entities().map_or(&[], Vec::as_slice));
+
+        SourcePost {
+            chat_id: message.chat().id(),
+            id: message.id(),
+            grouped_id: message.grouped_id(),
+            date: message.date().timestamp(),
+            edit_date: message.edit_date().map(|date| date.timestamp()),
+            text: text.to_string(),
+            markup,
+            kind: post_kind(message),
+            media: message.media(),
+        }
+    }
+
+    fn input_message(&self, text: &str) -> InputMessage {
+        match self.format {
+            TextFormat::Html => InputMessage::html(text),
+            TextFormat::Markdown => InputMessage::markdown(text),
+        }
+    }
+
+    fn input_media(&self, caption: &str) -> InputMedia {
+        match self.format {
+            TextFormat::Html => InputMedia::html(caption),
+            TextFormat::Markdown => InputMedia::markdown(caption),
+        }
+    }
+}
+
+fn parse(format: TextFormat, markup: &str) -> (String, Vec<tl::enums::MessageEntity>) {
+    match format {
+        TextFormat::Html => parse_html_message(markup),
+        TextFormat::Markdown => parse_markdown_message(markup),
+    }
+}
+
+fn generate(format: TextFormat, text: &str, entities: &[tl::enums::MessageEntity]) -> String {
+    match format {
+        TextFormat::Html => generate_html_message(text, entities),
+        TextFormat::Markdown => generate_markdown_message(text, entities),
+    }
+}
+
+impl Markup for TextFormat {
+    fn plain(&self, markup: &str) -> String {
+        parse(*self, markup).0
+    }
+
+    fn canonical(&self, markup: &str) -> String {
+        let (text, entities) = parse(*self, markup);
+        generate(*self, &text, &entities)
+    }
+
+    fn escape(&self, text: &str) -> String {
+        generate(*self, text, &[])
+    }
+}
+
+/// Вид поста по сырому вложению: высокоуровневый `Media` не различает
+/// голосовые, кружки и GIF среди документов
+fn post_kind(message: &Message) -> PostKind {
+    use tl::enums::MessageMedia;
+
+    match &message.raw.media {
+        None | Some(MessageMedia::Empty) => PostKind::Text,
+        Some(MessageMedia::Photo(_)) => PostKind::Photo,
+        Some(MessageMedia::Document(media)) => document_kind(media),
+        Some(MessageMedia::WebPage(media)) => match &media.webpage {
+            tl::enums::WebPage::Page(page) => PostKind::WebPage {
+                url: page.url.clone(),
+                title: page.title.clone(),
+                description: page.description.clone(),
+            },
+            // Превью ещё не готово или пустое
+            _ => PostKind::Text,
+        },
+        Some(MessageMedia::Poll(media)) => {
+            let tl::enums::Poll::Poll(poll) = &media.poll;
+            PostKind::Poll {
+                question: text_with_entities(&poll.question),
+                answers: poll
+                    .answers
+                    .iter()
+                    .map(|tl::enums::PollAnswer::Answer(answer)| text_with_entities(&answer.text))
+                    .collect(),
+            }
+        }
+        Some(MessageMedia::Geo(tl::types::MessageMediaGeo { geo, .. }))
+        | Some(MessageMedia::GeoLive(tl::types::MessageMediaGeoLive { geo, .. })) => match geo {
+            tl::enums::GeoPoint::Point(point) => PostKind::Geo {
+                latitude: point.lat,
+                longitude: point.long,
+            },
+            tl::enums::GeoPoint::Empty => PostKind::Unsupported,
+        },
+        Some(MessageMedia::Venue(venue)) => PostKind::Venue {
+            title: venue.title.clone(),
+            address: venue.address.clone(),
+        },
+        Some(MessageMedia::Contact(contact)) => PostKind::Contact {
+            name: format!("{} {}", contact.first_name, contact.last_name).trim().to_string(),
+            phone: contact.phone_number.clone(),
+        },
+        Some(MessageMedia::Dice(dice)) => PostKind::Dice {
+            emoji: dice.emoticon.clone(),
+        },
+        Some(_) => PostKind::Unsupported,
+    }
+}
+
+pub(super) fn document_kind(media: &tl::types::MessageMediaDocument) -> PostKind {
+    use tl::enums::DocumentAttribute;
+
+    let Some(tl::enums::Document::Document(document)) = &media.document else {
+        return PostKind::Unsupported;
+    };
+
+    let (mut sticker, mut animated, mut round, mut video) = (None, false, false, false);
+    let (mut audio, mut voice, mut file_name) = (None, false, None);
+    for attribute in &document.attributes {
+        match attribute {
+            DocumentAttribute::Sticker(attribute) => sticker = Some(attribute.alt.clone()),
+            DocumentAttribute::Animated => animated = true,
+            DocumentAttribute::Video(attribute) => {
+                video = true;
+                round |= attribute.round_message;
+            }
+            DocumentAttribute::Audio(attribute) => {
+                voice |= attribute.voice;
+                audio = Some((attribute.title.clone(), attribute.performer.clone()));
+            }
+            DocumentAttribute::Filename(attribute) => file_name = Some(attribute.file_name.clone()),
+            _ => {}
+        }
+    }
+
+    // У видео-стикеров и GIF тоже есть атрибут видео, поэтому порядок важен
+    match (sticker, audio) {
+        (Some(emoji), _) => PostKind::Sticker { emoji },
+        _ if animated => PostKind::Animation,
+        _ if round => PostKind::VideoNote,
+        _ if voice => PostKind::Voice,
+        (None, Some((title, performer))) => PostKind::Audio { title, performer },
+        _ if video => PostKind::Video,
+        _ => PostKind::Document { file_name },
+    }
+}
+
+fn text_with_entities(text: &tl::enums::TextWithEntities) -> String {
+    let tl::enums::TextWithEntities::Entities(text) = text;
+    text.text.clone()
+}
+
+/// Правка, которая ничего не меняет, для нас успешна
+fn not_modified_ok(result: std::result::Result<(), InvocationError>) -> Result<()> {
+    match result {
+        Err(InvocationError::Rpc(rpc)) if rpc.name == "MESSAGE_NOT_MODIFIED" => Ok(()),
+        result => Ok(result?),
+    }
+}
+
+impl From<InvocationError> for TransportError {
+    fn from(e: InvocationError) -> Self {
+        match e {
+            InvocationError::Rpc(rpc) if rpc.name == "FLOOD_WAIT" => {
+                TransportError::FloodWait(rpc.value.unwrap_or(10))
+            }
+            InvocationError::Rpc(rpc) => TransportError::Rpc(rpc.name),
+            other => TransportError::Other(other.to_string()),
+        }
+    }
+}
+
+impl ChatTransport for TelegramTransport {
+    type Media = Media;
+
+    async fn resolve_username(&self, username: &str) -> Result<Option<ResolvedChat>> {
+        let chat = self.client.resolve_username(username).await?;
+
+        Ok(chat.map(|chat| {
+            self.chats.lock().unwrap().insert(chat.id(), chat.pack());
+            ResolvedChat {
+                id: chat.id(),
+                name: chat.name().to_string(),
+            }
+        }))
+    }
+
+    async fn history_page(
+        &self,
+        chat_id: i64,
+        offset_id: Option<i32>,
+        limit: usize,
+    ) -> Result<Vec<SourcePost<Media>>> {
+        let chat = self.packed(chat_id)?;
+        let mut messages = self.client.iter_messages(chat).limit(limit);
+        if let Some(offset_id) = offset_id {
+            messages = messages.offset_id(offset_id);
+        }
+
+        let mut posts = Vec::new();
+        while let Some(message) = messages.next().await? {
+            posts.push(self.to_post(&message));
+        }
+        Ok(posts)
+    }
+
+    async fn posts_by_id(&self, chat_id: i64, ids: &[i32]) -> Result<Vec<SourcePost<Media>>> {
+        let chat = self.packed(chat_id)?;
+        let messages = self.client.get_messages_by_id(chat, ids).await?;
+        Ok(messages.iter().flatten().map(|message| self.to_post(message)).collect())
+    }
+
+    async fn download_image(&self, media: &Media, max_bytes: usize) -> Result<Option<MediaFile>> {
+        let mime = match media {
+            // Telegram пережимает фото в JPEG
+            Media::Photo(_) => "image/jpeg".to_string(),
+            Media::Document(document) => match document.mime_type() {
+                Some(mime) if IMAGE_MIME_TYPES.contains(&mime) && document.size() <= max_bytes as i64 => {
+                    mime.to_string()
+                }
+                _ => return Ok(None),
+            },
+            _ => return Ok(None),
+        };
+
+        let mut download = self.client.iter_download(&Downloadable::Media(media.clone()));
+        let mut bytes = Vec::new();
+        while let Some(chunk) = download.next().await? {
+            bytes.extend_from_slice(&chunk);
+            if bytes.len() > max_bytes {
+                return Ok(None);
+            }
+        }
+        Ok(Some(MediaFile { mime, bytes }))
+    }
+
+    async fn next_update(&self) -> Result<ChatUpdate<Media>> {
+        match self.client.next_update().await? {
+            Update::NewMessage(message) => Ok(ChatUpdate::NewPost(self.to_post(&message))),
+            Update::MessageEdited(message) => Ok(ChatUpdate::EditedPost(self.to_post(&message))),
+            // Без id канала (личные чаты и группы) удаление не сопоставить с источником
+            Update::MessageDeleted(deletion) => match deletion.channel_id() {
+                Some(chat_id) => Ok(ChatUpdate::DeletedPosts {
+                    chat_id,
+                    ids: deletion.messages().to_vec(),
+                }),
+                None => Ok(ChatUpdate::Other),
+            },
+            _ => Ok(ChatUpdate::Other),
+        }
+    }
+
+    fn text_len(&self, markup: &str) -> usize {
+        split::utf16_len(&self.format.plain(markup))
+    }
+
+    fn split_text(&self, markup: &str, first_limit: usize, limit: usize) -> Vec<String> {
+        split::split(&self.format, markup, first_limit, limit)
+    }
+
+    async fn send_text(&self, chat_id: i64, text: &str, reply_to: Option<i32>, link_preview: bool) -> Result<i32> {
+        let chat = self.packed(chat_id)?;
+        let message = self.input_message(text).reply_to(reply_to).link_preview(link_preview);
+        let sent = self.client.send_message(chat, message).await?;
+        Ok(sent.id())
+    }
+
+    async fn send_media(&self, chat_id: i64, media: &Media, caption: &str) -> Result<i32> {
+        let chat = self.packed(chat_id)?;
+        let sent = match self
+            .client
+            .send_message(chat, self.input_message(caption).copy_media(media))
+            .await
+        {
+            Err(e) if is_forwards_restricted(&e) && self.reuploader.enabled() => {
+                let reuploaded = self.reuploader.reupload(&self.client, media).await?;
+                self.client
+                    .send_message(chat, reuploaded.attach_to_message(self.input_message(caption)))
+                    .await?
+            }
+            sent => sent?,
+        };
+        Ok(sent.id())
+    }
+
+    async fn send_album(&self, chat_id: i64, items: Vec<AlbumItem<Media>>) -> Result<Vec<i32>> {
+        let chat = self.packed(chat_id)?;
+        let media = items
+            .iter()
+            .map(|item| self.input_media(&item.caption).copy_media(&item.media))
+            .collect();
+
+        let sent = match self.client.send_album(chat, media).await {
+            Err(e) if is_forwards_restricted(&e) && self.reuploader.enabled() => {
+                let mut media = Vec::with_capacity(items.len());
+                for item in &items {
+                    let reuploaded = self.reuploader.reupload(&self.client, &item.media).await?;
+                    media.push(reuploaded.attach_to_media(self.input_media(&item.caption)));
+                }
+                self.client.send_album(chat, media).await?
+            }
+            sent => sent?,
+        };
+        Ok(sent.into_iter().flatten().map(|m| m.id()).collect())
+    }
+
+    async fn edit_text(&self, chat_id: i64, id: i32, text: &str, link_preview: bool) -> Result<()> {
+        let chat = self.packed(chat_id)?;
+        let message = self.input_message(text).link_preview(link_preview);
+        not_modified_ok(self.client.edit_message(chat, id, message).await)
+    }
+
+    async fn edit_media(&self, chat_id: i64, id: i32, media: &Media, caption: &str) -> Result<()> {
+        let chat = self.packed(chat_id)?;
+        match self
+            .client
+            .edit_message(chat, id, self.input_message(caption).copy_media(media))
+            .await
+        {
+            Err(e) if is_forwards_restricted(&e) && self.reuploader.enabled() => {
+                let reuploaded = self.reuploader.reupload(&self.client, media).await?;
+                let message = reuploaded.attach_to_message(self.input_message(caption));
+                not_modified_ok(self.client.edit_message(chat, id, message).await)
+            }
+            result => not_modified_ok(result),
+        }
+    }
+
+    async fn delete(&self, chat_id: i64, ids: &[i32]) -> Result<()> {
+        let chat = self.packed(chat_id)?;
+        self.client.delete_messages(chat, ids).await?;
+        Ok(())
+    }
+
+    async fn forward(&self, chat_id: i64, from_chat_id: i64, ids: &[i32]) -> Result<Vec<i32>> {
+        let chat = self.packed(chat_id)?;
+        let from = self.packed(from_chat_id)?;
+        let sent = self.client.forward_messages(chat, ids, from).await?;
+        Ok(sent.into_iter().flatten().map(|m| m.id()).collect())
+    }
+}